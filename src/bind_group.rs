@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Keeps a bind group and its layout in sync by describing each binding once.
+#[derive(Default)]
+pub struct BindGroupBuilder<'a> {
+    label: Option<&'a str>,
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    entries: Vec<wgpu::BindGroupEntry<'a>>,
+}
+
+impl<'a> BindGroupBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(&mut self, label: &'a str) -> &mut Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn texture(&mut self, binding: u32, visibility: wgpu::ShaderStages, view: &'a wgpu::TextureView) -> &mut Self {
+        self.entry(
+            binding,
+            visibility,
+            wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            wgpu::BindingResource::TextureView(view),
+        )
+    }
+
+    // A cubemap, e.g. from Texture::from_cubemap, sampled with a direction
+    pub fn cube_texture(&mut self, binding: u32, visibility: wgpu::ShaderStages, view: &'a wgpu::TextureView) -> &mut Self {
+        self.entry(
+            binding,
+            visibility,
+            wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            wgpu::BindingResource::TextureView(view),
+        )
+    }
+
+    pub fn sampler(&mut self, binding: u32, visibility: wgpu::ShaderStages, sampler: &'a wgpu::Sampler) -> &mut Self {
+        self.entry(
+            binding,
+            visibility,
+            // This should match the filterable field of the texture entry
+            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            wgpu::BindingResource::Sampler(sampler),
+        )
+    }
+
+    // The texture and sampler entries for a sampler that only uses Nearest
+    // filtering, e.g. Texture::nearest_sampler. Using a Linear sampler here
+    // is a validation error.
+    pub fn unfiltered_texture(&mut self, binding: u32, visibility: wgpu::ShaderStages, view: &'a wgpu::TextureView) -> &mut Self {
+        self.entry(
+            binding,
+            visibility,
+            wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            wgpu::BindingResource::TextureView(view),
+        )
+    }
+
+    pub fn non_filtering_sampler(&mut self, binding: u32, visibility: wgpu::ShaderStages, sampler: &'a wgpu::Sampler) -> &mut Self {
+        self.entry(
+            binding,
+            visibility,
+            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+            wgpu::BindingResource::Sampler(sampler),
+        )
+    }
+
+    pub fn uniform(&mut self, binding: u32, visibility: wgpu::ShaderStages, buffer: &'a wgpu::Buffer) -> &mut Self {
+        self.entry(
+            binding,
+            visibility,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            buffer.as_entire_binding(),
+        )
+    }
+
+    // A uniform whose offset into `buffer` is picked when the group is bound,
+    // through the offsets passed to set_bind_group. Each offset exposes
+    // `size` bytes to the shader.
+    pub fn uniform_dynamic(
+        &mut self,
+        binding: u32,
+        visibility: wgpu::ShaderStages,
+        buffer: &'a wgpu::Buffer,
+        size: wgpu::BufferSize,
+    ) -> &mut Self {
+        self.entry(
+            binding,
+            visibility,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: Some(size),
+            },
+            wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer,
+                offset: 0,
+                size: Some(size),
+            }),
+        )
+    }
+
+    // Escape hatch for binding types the helpers above don't cover.
+    pub fn entry(&mut self, binding: u32, visibility: wgpu::ShaderStages, ty: wgpu::BindingType, resource: wgpu::BindingResource<'a>) -> &mut Self {
+        self.layout_entries.push(wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty,
+            count: None,
+        });
+        self.entries.push(wgpu::BindGroupEntry {
+            binding,
+            resource,
+        });
+        self
+    }
+
+    pub fn build(&self, device: &wgpu::Device) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+        let layout = self.build_layout(device);
+        let bind_group = self.build_bind_group(device, &layout);
+
+        (layout, bind_group)
+    }
+
+    pub fn build_layout(&self, device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let layout_label = self.label.map(|label| format!("{}_layout", label));
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &self.layout_entries,
+            label: layout_label.as_deref(),
+        })
+    }
+
+    // Creates the bind group against an existing layout, e.g. one shared
+    // by several groups that only differ in their resources.
+    pub fn build_bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &self.entries,
+            label: self.label,
+        })
+    }
+
+    // Like build, but reuses a layout from `cache` when one with the same
+    // entries was created before.
+    pub fn build_cached(&self, device: &wgpu::Device, cache: &mut LayoutCache) -> (Rc<wgpu::BindGroupLayout>, wgpu::BindGroup) {
+        let layout_label = self.label.map(|label| format!("{}_layout", label));
+        let layout = cache.get_or_create(device, &self.layout_entries, layout_label.as_deref());
+        let bind_group = self.build_bind_group(device, &layout);
+
+        (layout, bind_group)
+    }
+}
+
+// Hands out one bind group layout per distinct set of entries, so
+// pipelines and bind groups with identical layouts share a single object.
+//
+// BindGroupLayoutEntry is Hash and Eq, so the key is simply the entries
+// sorted by binding, making the order they were added in irrelevant. The
+// label isn't part of the key: layouts that only differ in their label are
+// interchangeable, and the first label used sticks.
+#[derive(Default)]
+pub struct LayoutCache {
+    layouts: HashMap<Vec<wgpu::BindGroupLayoutEntry>, Rc<wgpu::BindGroupLayout>>,
+}
+
+impl LayoutCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &wgpu::Device,
+        entries: &[wgpu::BindGroupLayoutEntry],
+        label: Option<&str>,
+    ) -> Rc<wgpu::BindGroupLayout> {
+        let mut key = entries.to_vec();
+        key.sort_by_key(|entry| entry.binding);
+
+        self.layouts
+            .entry(key)
+            .or_insert_with_key(|key| {
+                Rc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: key,
+                    label,
+                }))
+            })
+            .clone()
+    }
+
+    // Number of distinct layouts created so far
+    pub fn len(&self) -> usize {
+        self.layouts.len()
+    }
+}
@@ -1,4 +1,4 @@
-use winit::event::{WindowEvent, KeyboardInput, VirtualKeyCode, ElementState};
+use winit::event::{WindowEvent, KeyboardInput, VirtualKeyCode, ElementState, MouseButton, MouseScrollDelta};
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -8,22 +8,200 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        // Vertical field of view in degrees
+        fovy: f32,
+        znear: f32,
+        zfar: f32,
+    },
+    // left and right are multiplied by the camera's aspect ratio, so they
+    // should be given for a square viewport. That way resizing the window
+    // widens the visible area instead of stretching the scene.
+    Orthographic {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+// Flips the z axis, turning a left-handed view space into a right-handed one
+#[rustfmt::skip]
+const FLIP_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+// Maps depth d to 1 - d in clip space, so near ends up at 1 and far at 0
+#[rustfmt::skip]
+const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+// Which way the world's axes point, decides how the camera maps it onto
+// the screen.
+//
+// RightHanded (the default) is OpenGL style: with +Y up and +X to the
+// right, +Z points out of the screen and the camera looks down -Z in view
+// space. Triangles wound counter-clockwise in the mesh show up
+// counter-clockwise on screen, which the scene pipeline treats as the
+// front face (FrontFace::Ccw, culling Back).
+//
+// LeftHanded is DirectX style: +Z points into the screen and the camera
+// looks down +Z. Compared to RightHanded the scene is mirrored along Z, and
+// with it the on-screen winding of every triangle. Left-handed assets
+// usually wind their front faces clockwise, which comes out clockwise on
+// screen, so a pipeline drawing them with culling needs FrontFace::Cw.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    #[default]
+    RightHanded,
+    LeftHanded,
+}
+
+#[derive(Clone)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
     pub up: cgmath::Vector3<f32>,
     pub aspect: f32,
-    pub fovy: f32,
-    pub znear: f32,
-    pub zfar: f32,
+    pub projection: Projection,
+    pub coordinate_system: CoordinateSystem,
+    // Puts the near plane at depth 1 and the far plane at 0. Floats are
+    // most precise near 0, which reverse-Z spends on the distance where
+    // perspective squeezes depth values together, so far away geometry
+    // z-fights much less. Only the projection changes here, the depth test
+    // has to be flipped to Greater and the depth buffer cleared to 0 to
+    // match, which State::set_reverse_z does.
+    pub reverse_z: bool,
 }
 
 impl Camera {
+    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        match self.coordinate_system {
+            CoordinateSystem::RightHanded => cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up),
+            CoordinateSystem::LeftHanded => cgmath::Matrix4::look_at_lh(self.eye, self.target, self.up),
+        }
+    }
+
+    // Already converted to wgpu's clip space
+    pub fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let proj = match self.projection {
+            Projection::Perspective { fovy, znear, zfar } => {
+                cgmath::perspective(cgmath::Deg(fovy), self.aspect, znear, zfar)
+            }
+            Projection::Orthographic { left, right, bottom, top, near, far } => {
+                cgmath::ortho(left * self.aspect, right * self.aspect, bottom, top, near, far)
+            }
+        };
+
+        // cgmath only builds right-handed projections, so a left-handed view
+        // space has its z flipped back before projecting. Either way depth
+        // ends up in wgpu's 0..1 range.
+        let proj = match self.coordinate_system {
+            CoordinateSystem::RightHanded => OPENGL_TO_WGPU_MATRIX * proj,
+            CoordinateSystem::LeftHanded => OPENGL_TO_WGPU_MATRIX * proj * FLIP_Z_MATRIX,
+        };
+
+        if self.reverse_z {
+            REVERSE_Z_MATRIX * proj
+        } else {
+            proj
+        }
+    }
+
+    // Turns a value read from the depth buffer back into the distance from
+    // the camera along its view direction, undoing reverse-Z and the
+    // perspective divide. Cleared pixels come out at the far plane.
+    pub fn linearize_depth(&self, depth: f32) -> f32 {
+        let depth = if self.reverse_z { 1.0 - depth } else { depth };
+        let (near, far) = self.clip_planes();
+        match self.projection {
+            Projection::Perspective { .. } => near * far / (far - depth * (far - near)),
+            Projection::Orthographic { .. } => near + depth * (far - near),
+        }
+    }
+
+    pub fn clip_planes(&self) -> (f32, f32) {
+        match self.projection {
+            Projection::Perspective { znear, zfar, .. } => (znear, zfar),
+            Projection::Orthographic { near, far, .. } => (near, far),
+        }
+    }
+
+    // A tighter depth range gives the depth buffer more precision, which
+    // reduces z-fighting. Invalid ranges are ignored with a warning.
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) {
+        if !(0.0 < near && near < far) {
+            log::warn!("Invalid clip planes {}..{}, they need 0 < near < far", near, far);
+            return;
+        }
+
+        match &mut self.projection {
+            Projection::Perspective { znear, zfar, .. } => {
+                *znear = near;
+                *zfar = far;
+            }
+            Projection::Orthographic { near: ortho_near, far: ortho_far, .. } => {
+                *ortho_near = near;
+                *ortho_far = far;
+            }
+        }
+    }
+
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        self.build_projection_matrix() * self.build_view_matrix()
+    }
+
+    // The planes bounding what the camera sees, in world space, in the
+    // order left, right, bottom, top, near, far. Extracted from the
+    // view-projection matrix (Gribb & Hartmann): a point is visible when
+    // -w <= x, y <= w and 0 <= z <= w in clip space, and each of those
+    // inequalities is a plane. With reverse_z near and far trade places,
+    // but together they still bound the same volume.
+    pub fn frustum_planes(&self) -> [Plane; 6] {
+        use cgmath::Matrix;
+
+        let view_proj = self.build_view_projection_matrix();
+        let [x, y, z, w] = [0, 1, 2, 3].map(|row| view_proj.row(row));
+        [w + x, w - x, w + y, w - y, z, w - z].map(Plane::from_vector)
+    }
+}
+
+// The points p with normal.dot(p) + d = 0. The normal points into the
+// frustum for the planes from Camera::frustum_planes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: cgmath::Vector3<f32>,
+    pub d: f32,
+}
 
-        OPENGL_TO_WGPU_MATRIX * proj * view
+impl Plane {
+    // Normalized, so distance is in world units
+    fn from_vector(plane: cgmath::Vector4<f32>) -> Self {
+        use cgmath::InnerSpace;
+
+        let length = plane.truncate().magnitude();
+        Self {
+            normal: plane.truncate() / length,
+            d: plane.w / length,
+        }
+    }
+
+    // Positive on the side the normal points to
+    pub fn distance(&self, point: cgmath::Point3<f32>) -> f32 {
+        use cgmath::{EuclideanSpace, InnerSpace};
+
+        self.normal.dot(point.to_vec()) + self.d
     }
 }
 
@@ -32,6 +210,9 @@ impl Camera {
 // This is so we can store this in a buffer
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+    // Needed for specular lighting. A vec4 because uniforms
+    // have to be 16 byte aligned.
+    view_position: [f32; 4],
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
@@ -41,31 +222,153 @@ impl CameraUniform {
     pub fn new() -> Self {
         use cgmath::SquareMatrix;
         Self {
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_position = camera.eye.to_homogeneous().into();
         self.view_proj = camera.build_view_projection_matrix().into();
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Movement {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// Which keys move the camera, for CameraController and FpsController.
+// Every action can have several keys, the defaults are WASD plus the
+// arrow keys, with E and Q for up and down. A key bound to more than one
+// action only does the first in this order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyBindings {
+    pub forward: Vec<VirtualKeyCode>,
+    pub back: Vec<VirtualKeyCode>,
+    pub left: Vec<VirtualKeyCode>,
+    pub right: Vec<VirtualKeyCode>,
+    pub up: Vec<VirtualKeyCode>,
+    pub down: Vec<VirtualKeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: vec![VirtualKeyCode::W, VirtualKeyCode::Up],
+            back: vec![VirtualKeyCode::S, VirtualKeyCode::Down],
+            left: vec![VirtualKeyCode::A, VirtualKeyCode::Left],
+            right: vec![VirtualKeyCode::D, VirtualKeyCode::Right],
+            up: vec![VirtualKeyCode::E],
+            down: vec![VirtualKeyCode::Q],
+        }
+    }
+}
+
+impl KeyBindings {
+    fn movement(&self, keycode: VirtualKeyCode) -> Option<Movement> {
+        [
+            (&self.forward, Movement::Forward),
+            (&self.back, Movement::Back),
+            (&self.left, Movement::Left),
+            (&self.right, Movement::Right),
+            (&self.up, Movement::Up),
+            (&self.down, Movement::Down),
+        ]
+        .into_iter()
+        .find(|(keys, _)| keys.contains(&keycode))
+        .map(|(_, movement)| movement)
+    }
+}
+
+// Which movement keys are held down
+#[derive(Default)]
+struct MovementKeys {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
+
+impl MovementKeys {
+    fn set(&mut self, movement: Movement, is_pressed: bool) {
+        let key = match movement {
+            Movement::Forward => &mut self.forward,
+            Movement::Back => &mut self.back,
+            Movement::Left => &mut self.left,
+            Movement::Right => &mut self.right,
+            Movement::Up => &mut self.up,
+            Movement::Down => &mut self.down,
+        };
+        *key = is_pressed;
+    }
+}
+
+// Which controller drives State's camera, see State::set_camera_mode
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    // CameraController
+    #[default]
+    Keyboard,
+    // FpsController
+    Fps,
+    // OrbitController
+    Orbit,
+}
+
+// The slowest the camera can be set to, so it can't freeze
+const MIN_CAMERA_SPEED: f32 = 0.01;
+// How much +/- change the speed by per press
+const CAMERA_SPEED_STEP: f32 = 1.25;
+
 pub struct CameraController {
     speed: f32,
-    is_forward_pressed: bool,
-    is_backward_pressed: bool,
-    is_left_pressed: bool,
-    is_right_pressed: bool,
+    // Applied while shift is held
+    speed_multiplier: f32,
+    is_sprint_pressed: bool,
+    bindings: KeyBindings,
+    pressed: MovementKeys,
 }
 
 impl CameraController {
     pub fn new(speed: f32) -> Self {
         Self {
-            speed,
-            is_forward_pressed: false,
-            is_backward_pressed: false,
-            is_left_pressed: false,
-            is_right_pressed: false,
+            speed: speed.max(MIN_CAMERA_SPEED),
+            speed_multiplier: 3.0,
+            is_sprint_pressed: false,
+            bindings: KeyBindings::default(),
+            pressed: MovementKeys::default(),
+        }
+    }
+
+    // Keys held while the bindings change stay down until released
+    // under the old ones, so they're all let go here
+    pub fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.bindings = bindings;
+        self.pressed = MovementKeys::default();
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.max(MIN_CAMERA_SPEED);
+    }
+
+    pub fn set_speed_multiplier(&mut self, speed_multiplier: f32) {
+        self.speed_multiplier = speed_multiplier;
+    }
+
+    // The speed the camera currently moves at, including sprinting
+    pub fn speed(&self) -> f32 {
+        if self.is_sprint_pressed {
+            self.speed * self.speed_multiplier
+        } else {
+            self.speed
         }
     }
 
@@ -80,21 +383,26 @@ impl CameraController {
                 ..
             } => {
                 let is_pressed = *state == ElementState::Pressed;
+                if let Some(movement) = self.bindings.movement(*keycode) {
+                    self.pressed.set(movement, is_pressed);
+                    return true;
+                }
+
                 match keycode {
-                    VirtualKeyCode::W | VirtualKeyCode::Up => {
-                        self.is_forward_pressed = is_pressed;
+                    VirtualKeyCode::LShift | VirtualKeyCode::RShift => {
+                        self.is_sprint_pressed = is_pressed;
                         true
                     }
-                    VirtualKeyCode::A | VirtualKeyCode::Left => {
-                        self.is_left_pressed = is_pressed;
+                    VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
+                        if is_pressed {
+                            self.set_speed(self.speed * CAMERA_SPEED_STEP);
+                        }
                         true
                     }
-                    VirtualKeyCode::S | VirtualKeyCode::Down => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::D | VirtualKeyCode::Right => {
-                        self.is_right_pressed = is_pressed;
+                    VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                        if is_pressed {
+                            self.set_speed(self.speed / CAMERA_SPEED_STEP);
+                        }
                         true
                     }
                     _ => false,
@@ -106,18 +414,19 @@ impl CameraController {
 
     pub fn update_camera(&self, camera: &mut Camera) {
         use cgmath::InnerSpace;
+        let speed = self.speed();
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
         // Prevents glitching when camera gets too close to the
         // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+        if self.pressed.forward && forward_mag > speed {
+            camera.eye += forward_norm * speed;
         }
 
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+        if self.pressed.back {
+            camera.eye -= forward_norm * speed;
         }
 
         let right = forward_norm.cross(camera.up);
@@ -126,14 +435,289 @@ impl CameraController {
         let forward = camera.target - camera.eye;
         let forward_mag = forward.magnitude();
 
-        if self.is_right_pressed {
+        if self.pressed.right {
             // Rescale the distance between the target and eye so 
             // that it doesn't change. The eye therefore still 
             // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward + right * speed).normalize() * forward_mag;
+        }
+        if self.pressed.left {
+            camera.eye = camera.target - (forward - right * speed).normalize() * forward_mag;
+        }
+
+        // Up and down move the target along, so the view doesn't tilt
+        let up = camera.up.normalize() * speed;
+        if self.pressed.up {
+            camera.eye += up;
+            camera.target += up;
         }
-        if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+        if self.pressed.down {
+            camera.eye -= up;
+            camera.target -= up;
         }
     }
 }
+
+// Keeps the polar angle this far (in radians) away from the poles so the
+// view never lines up with the up vector and flips over.
+const ORBIT_POLE_MARGIN: f32 = 0.01;
+
+// Orbits the camera eye around its target while the left mouse button is
+// dragged and zooms in and out with the scroll wheel. Assumes +y is up.
+pub struct OrbitController {
+    rotate_speed: f32,
+    zoom_speed: f32,
+    min_distance: f32,
+    is_dragging: bool,
+    last_cursor: Option<winit::dpi::PhysicalPosition<f64>>,
+    drag_delta: (f32, f32),
+    scroll_delta: f32,
+}
+
+impl OrbitController {
+    // rotate_speed is in radians per pixel dragged, zoom_speed is the
+    // fraction of the current distance covered per scroll line.
+    pub fn new(rotate_speed: f32, zoom_speed: f32) -> Self {
+        Self {
+            rotate_speed,
+            zoom_speed,
+            min_distance: 0.1,
+            is_dragging: false,
+            last_cursor: None,
+            drag_delta: (0.0, 0.0),
+            scroll_delta: 0.0,
+        }
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.is_dragging = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(last)) = (self.is_dragging, self.last_cursor) {
+                    self.drag_delta.0 += (position.x - last.x) as f32;
+                    self.drag_delta.1 += (position.y - last.y) as f32;
+                }
+                self.last_cursor = Some(*position);
+                self.is_dragging
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Roughly one line per 20 pixels on touchpads
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0,
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera) {
+        use cgmath::InnerSpace;
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude();
+
+        let yaw = offset.x.atan2(offset.z) - self.drag_delta.0 * self.rotate_speed;
+        let polar = ((offset.y / distance).acos() - self.drag_delta.1 * self.rotate_speed)
+            .clamp(ORBIT_POLE_MARGIN, std::f32::consts::PI - ORBIT_POLE_MARGIN);
+        let distance = (distance * (1.0 - self.scroll_delta * self.zoom_speed)).max(self.min_distance);
+
+        camera.eye = camera.target + cgmath::Vector3::new(
+            distance * polar.sin() * yaw.sin(),
+            distance * polar.cos(),
+            distance * polar.sin() * yaw.cos(),
+        );
+
+        self.drag_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
+    }
+}
+
+// Free-look camera. WASD moves relative to where the camera is looking and
+// dragging with the right mouse button held turns it.
+pub struct FpsController {
+    // Units per second
+    speed: f32,
+    // Radians per unit of mouse motion
+    sensitivity: f32,
+    bindings: KeyBindings,
+    pressed: MovementKeys,
+    is_looking: bool,
+    mouse_delta: (f32, f32),
+}
+
+impl FpsController {
+    pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self {
+            speed,
+            sensitivity,
+            bindings: KeyBindings::default(),
+            pressed: MovementKeys::default(),
+            is_looking: false,
+            mouse_delta: (0.0, 0.0),
+        }
+    }
+
+    // See CameraController::set_bindings
+    pub fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.bindings = bindings;
+        self.pressed = MovementKeys::default();
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } => {
+                match self.bindings.movement(*keycode) {
+                    Some(movement) => {
+                        self.pressed.set(movement, *state == ElementState::Pressed);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Right,
+                ..
+            } => {
+                self.is_looking = *state == ElementState::Pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Takes the raw deltas from DeviceEvent::MouseMotion, which keep coming
+    // even when the cursor hits the edge of the window.
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        if self.is_looking {
+            self.mouse_delta.0 += dx as f32;
+            self.mouse_delta.1 += dy as f32;
+        }
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: std::time::Duration) {
+        use cgmath::InnerSpace;
+        let offset = camera.target - camera.eye;
+        let distance = offset.magnitude();
+        let direction = offset / distance;
+
+        // Stop just short of straight up or down so the view can't flip
+        let max_pitch = 89.0_f32.to_radians();
+        let yaw = direction.z.atan2(direction.x) + self.mouse_delta.0 * self.sensitivity;
+        let pitch = (direction.y.asin() - self.mouse_delta.1 * self.sensitivity).clamp(-max_pitch, max_pitch);
+        self.mouse_delta = (0.0, 0.0);
+
+        let direction = cgmath::Vector3::new(yaw.cos() * pitch.cos(), pitch.sin(), yaw.sin() * pitch.cos());
+        let right = direction.cross(camera.up).normalize();
+
+        let mut movement = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        if self.pressed.forward {
+            movement += direction;
+        }
+        if self.pressed.back {
+            movement -= direction;
+        }
+        if self.pressed.right {
+            movement += right;
+        }
+        if self.pressed.left {
+            movement -= right;
+        }
+        if self.pressed.up {
+            movement += camera.up;
+        }
+        if self.pressed.down {
+            movement -= camera.up;
+        }
+
+        // Normalized so moving diagonally isn't faster
+        if movement.magnitude2() > 0.0 {
+            camera.eye += movement.normalize() * self.speed * dt.as_secs_f32();
+        }
+        camera.target = camera.eye + direction * distance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Point3};
+
+    // At the origin looking down -z, so view space is world space
+    fn camera(projection: Projection) -> Camera {
+        Camera {
+            eye: (0.0, 0.0, 0.0).into(),
+            target: (0.0, 0.0, -1.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0,
+            projection,
+            coordinate_system: CoordinateSystem::RightHanded,
+            reverse_z: false,
+        }
+    }
+
+    fn assert_plane(plane: Plane, normal: [f32; 3], d: f32) {
+        let expected = Plane { normal: normal.into(), d };
+        assert!(
+            (plane.normal - expected.normal).magnitude2() < 1e-10 && (plane.d - expected.d).abs() < 1e-5,
+            "{:?} != {:?}",
+            plane,
+            expected,
+        );
+    }
+
+    #[test]
+    fn orthographic_frustum_planes() {
+        // The box -1 <= x, y <= 1, -10 <= z <= -1
+        let planes = camera(Projection::Orthographic {
+            left: -1.0,
+            right: 1.0,
+            bottom: -1.0,
+            top: 1.0,
+            near: 1.0,
+            far: 10.0,
+        }).frustum_planes();
+
+        assert_plane(planes[0], [1.0, 0.0, 0.0], 1.0);
+        assert_plane(planes[1], [-1.0, 0.0, 0.0], 1.0);
+        assert_plane(planes[2], [0.0, 1.0, 0.0], 1.0);
+        assert_plane(planes[3], [0.0, -1.0, 0.0], 1.0);
+        assert_plane(planes[4], [0.0, 0.0, -1.0], -1.0);
+        assert_plane(planes[5], [0.0, 0.0, 1.0], 10.0);
+    }
+
+    #[test]
+    fn perspective_frustum_planes() {
+        let planes = camera(Projection::Perspective {
+            fovy: 90.0,
+            znear: 1.0,
+            zfar: 10.0,
+        }).frustum_planes();
+        let inside = |point: Point3<f32>| planes.iter().all(|plane| plane.distance(point) >= 0.0);
+
+        assert!(inside(Point3::new(0.0, 0.0, -5.0)));
+        // With a 90 degree field of view the sides are at 45 degrees
+        assert!(inside(Point3::new(4.9, 0.0, -5.0)));
+        assert!(!inside(Point3::new(5.1, 0.0, -5.0)));
+        assert!(!inside(Point3::new(0.0, -5.1, -5.0)));
+        // In front of the near plane, behind the camera and past the far plane
+        assert!(!inside(Point3::new(0.0, 0.0, -0.5)));
+        assert!(!inside(Point3::new(0.0, 0.0, 5.0)));
+        assert!(!inside(Point3::new(0.0, 0.0, -11.0)));
+    }
+}
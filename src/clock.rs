@@ -0,0 +1,43 @@
+// std::time::Instant panics on wasm32-unknown-unknown, so on the web time
+// is measured with the browser's performance clock instead.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub use web::Instant;
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::ops::Sub;
+    use std::time::Duration;
+
+    // Milliseconds since the page was loaded
+    #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+    pub struct Instant(f64);
+
+    impl Instant {
+        pub fn now() -> Self {
+            let performance = web_sys::window()
+                .and_then(|window| window.performance())
+                .expect("Couldn't access window.performance");
+            Self(performance.now())
+        }
+
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            Duration::from_secs_f64(((self.0 - earlier.0) / 1000.0).max(0.0))
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Self::now().duration_since(*self)
+        }
+    }
+
+    impl Sub for Instant {
+        type Output = Duration;
+
+        fn sub(self, earlier: Instant) -> Duration {
+            self.duration_since(earlier)
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// Builds a compute pipeline from a shader module and an optional layout.
+// Without a layout wgpu derives one from the shader.
+pub struct ComputePipelineBuilder<'a> {
+    label: Option<&'a str>,
+    layout: Option<&'a wgpu::PipelineLayout>,
+    shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    entry_point: &'a str,
+}
+
+impl<'a> Default for ComputePipelineBuilder<'a> {
+    fn default() -> Self {
+        Self {
+            label: None,
+            layout: None,
+            shader: None,
+            entry_point: "main",
+        }
+    }
+}
+
+impl<'a> ComputePipelineBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(&mut self, label: &'a str) -> &mut Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn layout(&mut self, layout: &'a wgpu::PipelineLayout) -> &mut Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    pub fn shader(&mut self, shader: wgpu::ShaderModuleDescriptor<'a>) -> &mut Self {
+        self.shader = Some(shader);
+        self
+    }
+
+    pub fn entry_point(&mut self, entry_point: &'a str) -> &mut Self {
+        self.entry_point = entry_point;
+        self
+    }
+
+    // Consumes the stored shader descriptor, so the builder can only build once.
+    pub fn build(&mut self, device: &wgpu::Device) -> wgpu::ComputePipeline {
+        let shader = device.create_shader_module(self.shader.take().expect("No compute shader supplied"));
+
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: self.label,
+            layout: self.layout,
+            module: &shader,
+            entry_point: self.entry_point,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::util::DeviceExt;
+
+    const DOUBLE_SHADER: &str = "
+@group(0) @binding(0) var<storage, read_write> values: array<u32>;
+
+@compute @workgroup_size(1)
+fn double(@builtin(global_invocation_id) id: vec3<u32>) {
+    values[id.x] = values[id.x] * 2u;
+}
+";
+
+    // None when there's nothing to run compute shaders on, e.g. CI
+    // without a GPU, in which case the tests pass without checking anything
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        if !adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            return None;
+        }
+        pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: adapter.limits(),
+            },
+            None,
+        )).ok()
+    }
+
+    #[test]
+    fn builds_with_entry_point() {
+        let (device, queue) = match device() {
+            Some(device) => device,
+            None => {
+                eprintln!("No adapter with compute shaders, skipping");
+                return;
+            }
+        };
+
+        let input = [1u32, 2, 3, 4];
+        let size = std::mem::size_of_val(&input) as wgpu::BufferAddress;
+        let storage = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&input),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // No layout, so wgpu derives it from the "double" entry point
+        let pipeline = ComputePipelineBuilder::new()
+            .shader(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(DOUBLE_SHADER.into()),
+            })
+            .entry_point("double")
+            .build(&device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: storage.as_entire_binding(),
+            }],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            compute_pass.set_pipeline(&pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(input.len() as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&storage, 0, &readback, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().unwrap();
+
+        let output: Vec<u32> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        assert_eq!(output, [2, 4, 6, 8]);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::texture;
+
+// Enough for the axes and a few dozen boxes before the buffer has to grow
+const INITIAL_CAPACITY: usize = 1024;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl LineVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        0 => Float32x3, // Position
+        1 => Float32x3, // Color
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Line Buffer"),
+        size: (capacity * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    reverse_z: bool,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::include_wgsl!("debug_lines.wgsl"));
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Debug Line Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[LineVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        // Tested against the scene's depth so geometry hides the lines, but
+        // the lines don't write depth themselves
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: crate::depth_compare(wgpu::CompareFunction::LessEqual, reverse_z),
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        // Drawn in the scene pass, so this has to match its attachments
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// Lines for debug gizmos such as axes and bounding boxes. They accumulate
+// until clear() is called, so the usual pattern is to clear and re-add
+// them every frame.
+pub struct DebugLines {
+    vertices: Vec<LineVertex>,
+    buffer: wgpu::Buffer,
+    // How many vertices fit in buffer
+    capacity: usize,
+    // How many vertices were written by the last upload
+    uploaded: u32,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    reverse_z: bool,
+}
+
+impl DebugLines {
+    // `camera_layout` is the layout of the camera uniform, bound at group 0.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, format, sample_count, false);
+
+        Self {
+            vertices: Vec::new(),
+            buffer: create_vertex_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            uploaded: 0,
+            pipeline_layout,
+            pipeline,
+            format,
+            sample_count,
+            reverse_z: false,
+        }
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, self.format, sample_count, self.reverse_z);
+    }
+
+    // Has to match the camera, see Camera::reverse_z
+    pub fn set_reverse_z(&mut self, device: &wgpu::Device, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, self.format, self.sample_count, reverse_z);
+    }
+
+    pub fn line(&mut self, a: cgmath::Point3<f32>, b: cgmath::Point3<f32>, color: [f32; 3]) {
+        self.vertices.push(LineVertex { position: a.into(), color });
+        self.vertices.push(LineVertex { position: b.into(), color });
+    }
+
+    // The 12 edges of an axis aligned box
+    pub fn aabb(&mut self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>, color: [f32; 3]) {
+        let corner = |i: usize| cgmath::Point3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+
+        for i in 0..8 {
+            // Connect each corner to the neighbours that differ in one axis,
+            // only going upwards so every edge is added once
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    // Copies the accumulated lines to the GPU, growing the buffer if they
+    // don't fit. Has to be called before the pass that draws them is
+    // submitted.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.vertices.len() > self.capacity {
+            self.capacity = self.vertices.len().max(self.capacity * 2);
+            self.buffer = create_vertex_buffer(device, self.capacity);
+        }
+
+        if !self.vertices.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.vertices));
+        }
+        self.uploaded = self.vertices.len() as u32;
+    }
+
+    // Records the lines into a pass that has the scene's depth buffer
+    // attached.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup, camera_offset: wgpu::DynamicOffset) {
+        if self.uploaded == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[camera_offset]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.uploaded, 0..1);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::bind_group::BindGroupBuilder;
+
+// Depth24PlusStencil8 can't be copied out of, so the depth is drawn into a
+// texture of this format first
+const CAPTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+// Depth textures can be bound as unfilterable float ones, see depth_capture.wgsl
+fn depth_entry(multisampled: bool) -> wgpu::BindingType {
+    wgpu::BindingType::Texture {
+        multisampled,
+        view_dimension: wgpu::TextureViewDimension::D2,
+        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    multisampled: bool,
+) -> (wgpu::BindGroupLayout, wgpu::RenderPipeline) {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Depth Capture Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: depth_entry(multisampled),
+            count: None,
+        }],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Depth Capture Pipeline Layout"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Depth Capture Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: if multisampled { "fs_multisampled" } else { "fs_main" },
+            targets: &[Some(wgpu::ColorTargetState {
+                format: CAPTURE_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    (layout, pipeline)
+}
+
+// Reads the depth buffer back to the CPU, see State::capture_depth
+pub struct DepthCapture {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    // For multisampled depth buffers, which need a different texture binding
+    multisampled_layout: wgpu::BindGroupLayout,
+    multisampled_pipeline: wgpu::RenderPipeline,
+}
+
+impl DepthCapture {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("depth_capture.wgsl"));
+        let (layout, pipeline) = create_pipeline(device, &shader, false);
+        let (multisampled_layout, multisampled_pipeline) = create_pipeline(device, &shader, true);
+
+        Self {
+            layout,
+            pipeline,
+            multisampled_layout,
+            multisampled_pipeline,
+        }
+    }
+
+    // The raw depth of every pixel of `depth`, row by row from the top left.
+    // Multisampled textures give their first sample. Waits for the GPU, and
+    // returns None if the buffer can't be mapped because the device is gone.
+    pub fn capture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        depth: &wgpu::Texture,
+        size: winit::dpi::PhysicalSize<u32>,
+        sample_count: u32,
+    ) -> Option<Vec<f32>> {
+        let (layout, pipeline) = if sample_count > 1 {
+            (&self.multisampled_layout, &self.multisampled_pipeline)
+        } else {
+            (&self.layout, &self.pipeline)
+        };
+
+        // Only the depth aspect can be bound, the stencil is left out
+        let depth_view = depth.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let bind_group = BindGroupBuilder::new()
+            .label("Depth Capture Bind Group")
+            .entry(0, wgpu::ShaderStages::FRAGMENT, depth_entry(sample_count > 1), wgpu::BindingResource::TextureView(&depth_view))
+            .build_bind_group(device, layout);
+
+        let extent = wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Capture Texture"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: CAPTURE_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Rows copied into a buffer have to be padded to a multiple of 256 bytes
+        let unpadded_bytes_per_row = 4 * size.width;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Capture Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Depth Capture Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Capture Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // Every pixel is overwritten
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(size.height),
+                },
+            },
+            extent,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        if !matches!(receiver.recv(), Ok(Ok(()))) {
+            return None;
+        }
+
+        let mut depths = Vec::with_capacity((size.width * size.height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                depths.extend_from_slice(bytemuck::cast_slice(&row[..unpadded_bytes_per_row as usize]));
+            }
+        }
+        buffer.unmap();
+        Some(depths)
+    }
+}
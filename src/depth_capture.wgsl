@@ -0,0 +1,29 @@
+// Copies the depth buffer into an R32Float texture, which unlike the
+// depth texture itself can be copied into a buffer. One fragment per
+// pixel, each loading its own texel, so no filtering is involved. The
+// depth is bound as a plain float texture, with the value in red, since
+// the GL backend can't load from depth textures.
+
+// A single triangle that covers the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    return textureLoad(t_depth, vec2<i32>(position.xy), 0).r;
+}
+
+@group(0) @binding(0)
+var t_depth_multisampled: texture_multisampled_2d<f32>;
+
+// With multisampling the first sample stands in for the whole pixel
+@fragment
+fn fs_multisampled(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    return textureLoad(t_depth_multisampled, vec2<i32>(position.xy), 0).r;
+}
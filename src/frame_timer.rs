@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use crate::clock::Instant;
+
+// How much of the newest frame time goes into the smoothed average. Lower
+// values give a steadier readout that reacts more slowly.
+const SMOOTHING: f32 = 0.05;
+
+// Seconds since the State was created, for animating shaders, see
+// shader.wgsl. Padded to 16 bytes like the other small uniforms.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TimeUniform {
+    pub seconds: f32,
+    _padding: [f32; 3],
+}
+
+impl TimeUniform {
+    pub fn new(seconds: f32) -> Self {
+        Self {
+            seconds,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+pub struct FrameTimer {
+    last_frame: Instant,
+    // Exponential moving average of the frame time in seconds
+    smoothed_frame_time: f32,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self {
+            last_frame: Instant::now(),
+            smoothed_frame_time: 0.0,
+        }
+    }
+
+    // Marks the start of a new frame and returns the time since the last one.
+    pub fn tick(&mut self) -> Duration {
+        let now = Instant::now();
+        let dt = now - self.last_frame;
+        self.last_frame = now;
+
+        let seconds = dt.as_secs_f32();
+        self.smoothed_frame_time = if self.smoothed_frame_time == 0.0 {
+            seconds
+        } else {
+            self.smoothed_frame_time + (seconds - self.smoothed_frame_time) * SMOOTHING
+        };
+
+        dt
+    }
+
+    pub fn fps(&self) -> f32 {
+        if self.smoothed_frame_time > 0.0 {
+            1.0 / self.smoothed_frame_time
+        } else {
+            0.0
+        }
+    }
+
+    pub fn frame_time_ms(&self) -> f32 {
+        self.smoothed_frame_time * 1000.0
+    }
+}
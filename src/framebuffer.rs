@@ -0,0 +1,96 @@
+use crate::texture;
+
+// The multisampled color texture and the depth texture the scene is drawn
+// into. The depth texture's sample count has to match the color
+// attachment's, so both are created, and recreated, together.
+//
+// With a sample count of 1 there's no color texture: the scene is drawn
+// straight into the resolve target passed to color_attachment.
+pub struct Framebuffer {
+    sample_count: u32,
+    // Kept for the format and to recreate the textures in resize
+    config: wgpu::SurfaceConfiguration,
+    color: Option<wgpu::TextureView>,
+    depth: texture::Texture,
+}
+
+fn create_color_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    sample_count: u32,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Multisampled Framebuffer"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+impl Framebuffer {
+    // Sample counts wgpu can't create textures with are rejected. Whether the
+    // adapter can multisample (and resolve) the formats is up to the caller,
+    // see State::set_sample_count.
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> anyhow::Result<Self> {
+        if !matches!(sample_count, 1 | 2 | 4 | 8 | 16) {
+            anyhow::bail!("invalid sample count {}, expected 1, 2, 4, 8 or 16", sample_count);
+        }
+
+        let mut framebuffer = Self {
+            sample_count,
+            config: config.clone(),
+            color: None,
+            depth: texture::Texture::create_depth_texture(device, config, sample_count, "depth_texture"),
+        };
+        framebuffer.color = (sample_count > 1).then(|| create_color_view(device, config, sample_count));
+        Ok(framebuffer)
+    }
+
+    // Both textures have to match the target they're resolved into, so this
+    // follows State::resize
+    pub fn resize(&mut self, device: &wgpu::Device, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+        self.depth = texture::Texture::create_depth_texture(device, &self.config, self.sample_count, "depth_texture");
+        if self.sample_count > 1 {
+            self.color = Some(create_color_view(device, &self.config, self.sample_count));
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth.view
+    }
+
+    pub fn depth_texture(&self) -> &wgpu::Texture {
+        &self.depth.texture
+    }
+
+    // The view and resolve target of a color attachment drawing into this
+    // framebuffer, ending up in `resolve_target` either way
+    pub fn color_attachment<'a>(
+        &'a self,
+        resolve_target: &'a wgpu::TextureView,
+    ) -> (&'a wgpu::TextureView, Option<&'a wgpu::TextureView>) {
+        match &self.color {
+            Some(multisampled_view) => (multisampled_view, Some(resolve_target)),
+            None => (resolve_target, None),
+        }
+    }
+}
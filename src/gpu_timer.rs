@@ -0,0 +1,113 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+const QUERY_COUNT: u32 = 2;
+const QUERY_BUFFER_SIZE: wgpu::BufferAddress = QUERY_COUNT as wgpu::BufferAddress * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+// Measures how long the GPU spends on a frame with a pair of timestamp
+// queries. The results are read back asynchronously, so frames recorded
+// while a readback is still in flight are skipped rather than stalling.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    // The queries are resolved straight into this and mapped for reading
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    // Whether timestamps are being written this frame
+    recording: bool,
+    // Receives the result of mapping readback_buffer, Some while a map is
+    // in flight
+    pending: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    last_time: Option<Duration>,
+}
+
+impl GpuTimer {
+    // Returns None when the device wasn't created with Features::TIMESTAMP_QUERY
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Query Set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Readback Buffer"),
+            size: QUERY_BUFFER_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            recording: false,
+            pending: None,
+            last_time: None,
+        })
+    }
+
+    // Writing timestamps inside a pass needs WRITE_TIMESTAMP_INSIDE_PASSES,
+    // so begin and end go on the encoder around the frame's passes instead.
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.recording = self.pending.is_none();
+        if self.recording {
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.recording {
+            return;
+        }
+
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.readback_buffer, 0);
+    }
+
+    // Call once the encoder passed to begin and end has been submitted.
+    // Starts reading back this frame's timestamps and picks up the results
+    // of an earlier frame if they have arrived.
+    pub fn end_frame(&mut self, device: &wgpu::Device) {
+        if self.recording {
+            self.recording = false;
+
+            let (sender, receiver) = mpsc::channel();
+            self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+            self.pending = Some(receiver);
+        }
+
+        let Some(receiver) = &self.pending else {
+            return;
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return,
+            // The buffer was dropped along with the callback
+            Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+        };
+        self.pending = None;
+
+        if result.is_err() {
+            return;
+        }
+
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            let ticks = timestamps[1].saturating_sub(timestamps[0]);
+            self.last_time = Some(Duration::from_nanos((ticks as f64 * self.period as f64) as u64));
+        }
+        self.readback_buffer.unmap();
+    }
+
+    pub fn last_time(&self) -> Option<Duration> {
+        self.last_time
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+
+pub const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Reads the shader from disk, falling back to the copy embedded at compile
+// time if the file can't be read (e.g. the binary was moved).
+pub fn read_shader() -> String {
+    std::fs::read_to_string(SHADER_PATH).unwrap_or_else(|e| {
+        log::warn!("Couldn't read {}, using the embedded shader: {}", SHADER_PATH, e);
+        include_str!("shader.wgsl").to_string()
+    })
+}
+
+// Watches a file for modifications by polling its timestamp on a
+// background thread. The thread exits once the watcher is dropped.
+pub struct ShaderWatcher {
+    changed: Arc<AtomicBool>,
+}
+
+impl ShaderWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let changed = Arc::new(AtomicBool::new(false));
+        let flag = Arc::downgrade(&changed);
+
+        std::thread::spawn(move || watch(path, flag));
+
+        Self { changed }
+    }
+
+    // Returns true once for every detected change.
+    pub fn has_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+fn watch(path: PathBuf, flag: Weak<AtomicBool>) {
+    let modified = |path: &PathBuf| -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    };
+
+    let mut last_modified = modified(&path);
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+
+        let Some(flag) = flag.upgrade() else {
+            return;
+        };
+
+        let current = modified(&path);
+        if current != last_modified {
+            last_modified = current;
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::Read;
+use std::time::Duration;
+
+use anyhow::*;
+
+// Long enough for a large texture over a slow connection, short enough that
+// a server that stopped answering doesn't hang the caller
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A blocking HTTP GET of the whole body. http:// and https:// both work
+// and redirects are followed, anything other than a 2xx answer in the end
+// is an error.
+pub fn get(url: &str) -> Result<Vec<u8>> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build();
+    let response = match agent.get(url).call() {
+        Result::Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => bail!("Fetching {} failed with status {}", url, status),
+        Err(error) => return Err(error).with_context(|| format!("Couldn't fetch {}", url)),
+    };
+    // A redirect that wasn't followed, e.g. one without a Location
+    if !(200..300).contains(&response.status()) {
+        bail!("Fetching {} failed with status {}", url, response.status());
+    }
+
+    let mut bytes = Vec::new();
+    response.into_reader()
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("Couldn't read the body of {}", url))?;
+
+    Ok(bytes)
+}
@@ -1,402 +1,2939 @@
-use wgpu::{Backends, include_wgsl, util::DeviceExt};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
 use winit::{
 	event::*,
 	event_loop::{ControlFlow, EventLoop},
-	window::{WindowBuilder, Window},
+	window::{Fullscreen, WindowBuilder, Window},
 };
 
+mod bind_group;
 mod texture;
+mod uniform;
 mod camera;
+mod clock;
+mod light;
+mod material;
+mod mesh;
+mod compute;
+mod debug_lines;
+mod depth_capture;
+mod framebuffer;
+mod frame_timer;
+mod gpu_timer;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+mod http;
+mod model;
+mod particles;
+mod picking;
+mod post_process;
+pub mod render_stage;
+mod render_targets;
+mod skybox;
+mod sprite;
+mod text;
+mod transform;
+
+pub use camera::{CameraMode, CoordinateSystem, KeyBindings, OrbitController, Projection};
+pub use material::Material;
+pub use render_stage::RenderPassStage;
+pub use render_targets::{gbuffer_shader, GBUFFER_FORMATS};
+pub use sprite::Rect;
+pub use uniform::UniformArray;
+pub use texture::TextureFilter;
+
+#[cfg(target_arch="wasm32")]
+use wasm_bindgen::prelude::*;
+
+// The vertex of the textured meshes. Shaders reading it, like shader.wgsl,
+// take the position at @location(0), the texture coordinate at
+// @location(1) and the normal at @location(2). Use Vertex::desc as the
+// vertex buffer layout of your own pipelines.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+}
+
+impl Vertex {
+	pub const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+		0 => Float32x3,	// Position
+		1 => Float32x2, // Texture coordinate
+		2 => Float32x3, // Normal
+	];
+
+	pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+		use std::mem;
+
+		wgpu::VertexBufferLayout  {
+			array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+			step_mode: wgpu::VertexStepMode::Vertex,
+			attributes: &Self::ATTRIBS,
+		}
+	}
+}
+
+impl mesh::VertexPosition for Vertex {
+	fn position(&self) -> [f32; 3] {
+		self.position
+	}
+}
+
+// A vertex colored instead of textured, drawn by shader_color.wgsl. The
+// locations match Vertex with the color in place of the texture coordinate.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColorVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    pub normal: [f32; 3],
+}
+
+impl ColorVertex {
+	const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+		0 => Float32x3, // Position
+		1 => Float32x4, // Color
+		2 => Float32x3, // Normal
+	];
+
+	fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+		wgpu::VertexBufferLayout {
+			array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+			step_mode: wgpu::VertexStepMode::Vertex,
+			attributes: &Self::ATTRIBS,
+		}
+	}
+}
+
+impl mesh::VertexPosition for ColorVertex {
+	fn position(&self) -> [f32; 3] {
+		self.position
+	}
+}
+
+const VERTICES: &[Vertex] = &[
+    // Changed
+    Vertex { position: [-0.0868241, 0.49240386, 0.0], tex_coords: [0.4131759, 0.00759614], normal: [0.0, 0.0, 1.0], }, // A
+    Vertex { position: [-0.49513406, 0.06958647, 0.0], tex_coords: [0.0048659444, 0.43041354], normal: [0.0, 0.0, 1.0], }, // B
+    Vertex { position: [-0.21918549, -0.44939706, 0.0], tex_coords: [0.28081453, 0.949397], normal: [0.0, 0.0, 1.0], }, // C
+    Vertex { position: [0.35966998, -0.3473291, 0.0], tex_coords: [0.85967, 0.84732914], normal: [0.0, 0.0, 1.0], }, // D
+    Vertex { position: [0.44147372, 0.2347359, 0.0], tex_coords: [0.9414737, 0.2652641], normal: [0.0, 0.0, 1.0], }, // E
+];
+
+// The same pentagon with a color per corner
+const COLORED_VERTICES: &[ColorVertex] = &[
+    ColorVertex { position: [-0.0868241, 0.49240386, 0.0], color: [1.0, 0.0, 0.0, 1.0], normal: [0.0, 0.0, 1.0], }, // A
+    ColorVertex { position: [-0.49513406, 0.06958647, 0.0], color: [1.0, 1.0, 0.0, 1.0], normal: [0.0, 0.0, 1.0], }, // B
+    ColorVertex { position: [-0.21918549, -0.44939706, 0.0], color: [0.0, 1.0, 0.0, 1.0], normal: [0.0, 0.0, 1.0], }, // C
+    ColorVertex { position: [0.35966998, -0.3473291, 0.0], color: [0.0, 0.0, 1.0, 1.0], normal: [0.0, 0.0, 1.0], }, // D
+    ColorVertex { position: [0.44147372, 0.2347359, 0.0], color: [1.0, 0.0, 1.0, 1.0], normal: [0.0, 0.0, 1.0], }, // E
+];
+
+
+const INDICES: &[u32] = &[
+    0, 1, 4,
+    1, 2, 4,
+    2, 3, 4,
+];
+
+const NUM_INSTANCES_PER_ROW: u32 = 10;
+// How much PageUp / PageDown change the exposure by
+const EXPOSURE_STEP: f32 = 1.25;
+// Plenty for the per-frame uniforms, the belt allocates more chunks if needed
+const STAGING_BELT_CHUNK_SIZE: wgpu::BufferAddress = 1024;
+// The slot of State::camera_uniform the scene is drawn with
+const CAMERA_SLOT: usize = 0;
+const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
+
+struct Instance {
+	position: cgmath::Vector3<f32>,
+	rotation: cgmath::Quaternion<f32>,
+}
+
+impl Instance {
+	fn transform(&self) -> transform::Transform {
+		transform::Transform {
+			translation: self.position,
+			rotation: self.rotation,
+			..Default::default()
+		}
+	}
+
+	fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+		self.transform().to_matrix()
+	}
+
+	fn to_raw(&self) -> InstanceRaw {
+		InstanceRaw {
+			model: self.transform().to_raw(),
+		}
+	}
+}
+
+// How many meshes the last update found on and off screen, see
+// State::cull_stats
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullStats {
+	pub drawn: usize,
+	pub culled: usize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct InstanceRaw {
+	model: [[f32; 4]; 4],
+}
+
+impl InstanceRaw {
+	// A mat4 takes up four vertex slots, one vec4 per column. Locations
+	// 3 and 4 are left free for future per-vertex attributes.
+	const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+		5 => Float32x4,
+		6 => Float32x4,
+		7 => Float32x4,
+		8 => Float32x4,
+	];
+
+	fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+		use std::mem;
+
+		wgpu::VertexBufferLayout {
+			array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+			// The shader only moves on to the next instance once it has
+			// processed all of the vertices of the current one.
+			step_mode: wgpu::VertexStepMode::Instance,
+			attributes: &Self::ATTRIBS,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum InitError {
+	// No adapter is compatible with the window surface, or none at all
+	// when running headless
+	NoAdapter,
+	// The surface can't be used with the adapter at all, seen on some
+	// misconfigured Linux/Mesa setups
+	NoSupportedFormat,
+	RequestDevice(wgpu::RequestDeviceError),
+	Texture(anyhow::Error),
+	// State::new_headless was asked for a format capture_frame can't read
+	// back, see CAPTURE_FORMATS
+	UnsupportedFormat(wgpu::TextureFormat),
+}
+
+impl std::fmt::Display for InitError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			InitError::NoAdapter => write!(f, "no suitable graphics adapter found"),
+			InitError::NoSupportedFormat => write!(f, "the surface supports no texture formats on this adapter"),
+			InitError::RequestDevice(e) => write!(f, "failed to request device: {}", e),
+			InitError::Texture(e) => write!(f, "failed to load texture: {}", e),
+			InitError::UnsupportedFormat(format) => write!(f, "can't render headless to {:?}, expected one of {:?}", format, CAPTURE_FORMATS),
+		}
+	}
+}
+
+impl std::error::Error for InitError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			InitError::NoAdapter | InitError::NoSupportedFormat | InitError::UnsupportedFormat(_) => None,
+			InitError::RequestDevice(e) => Some(e),
+			InitError::Texture(e) => Some(e.as_ref()),
+		}
+	}
+}
+
+// The steps State::new_with_progress reports, in the order they happen.
+// Each one is reported as it starts, Done once the State is ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitPhase {
+	RequestingAdapter,
+	RequestingDevice,
+	LoadingTextures,
+	BuildingPipeline,
+	Done,
+}
+
+// The formats State::capture_frame can read back: 4 bytes per pixel, with
+// the blue and red channels swapped back for the Bgra ones
+pub const CAPTURE_FORMATS: [wgpu::TextureFormat; 4] = [
+	wgpu::TextureFormat::Rgba8Unorm,
+	wgpu::TextureFormat::Rgba8UnormSrgb,
+	wgpu::TextureFormat::Bgra8Unorm,
+	wgpu::TextureFormat::Bgra8UnormSrgb,
+];
+
+// What the scene gets rendered into: the window's surface, or a plain
+// texture when running headless.
+enum RenderTarget {
+	Surface(wgpu::Surface),
+	Offscreen(wgpu::Texture),
+}
+
+// Stands in for the surface texture when running headless. COPY_SRC lets
+// capture_frame read it back.
+fn create_offscreen_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+	device.create_texture(&wgpu::TextureDescriptor {
+		label: Some("Offscreen Target"),
+		size: wgpu::Extent3d {
+			width: config.width,
+			height: config.height,
+			depth_or_array_layers: 1,
+		},
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: config.format,
+		usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+	})
+}
+
+async fn request_device(
+	instance: &wgpu::Instance,
+	compatible_surface: Option<&wgpu::Surface>,
+	state_config: StateConfig,
+	progress: &mut dyn FnMut(InitPhase),
+) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), InitError> {
+	progress(InitPhase::RequestingAdapter);
+	let power_preference = match state_config.adapter {
+		AdapterSelector::PowerPreference(power_preference) => power_preference,
+		ref selector => match find_adapter(instance, state_config.backends, compatible_surface, selector) {
+			Some(adapter) => return finish_request_device(adapter, state_config, progress).await,
+			None => {
+				log::warn!("No adapter matches {:?}, using the default one", selector);
+				wgpu::PowerPreference::default()
+			}
+		},
+	};
+	let adapter = instance.request_adapter(
+		&wgpu::RequestAdapterOptions {
+			power_preference,
+			compatible_surface,
+			force_fallback_adapter: false,
+		},
+	).await.ok_or(InitError::NoAdapter)?;
+
+	finish_request_device(adapter, state_config, progress).await
+}
+
+// Index and NamePattern look through every adapter instead of letting
+// wgpu pick one. Adapters that can't present to the surface are skipped.
+#[cfg(not(target_arch = "wasm32"))]
+fn find_adapter(
+	instance: &wgpu::Instance,
+	backends: wgpu::Backends,
+	compatible_surface: Option<&wgpu::Surface>,
+	selector: &AdapterSelector,
+) -> Option<wgpu::Adapter> {
+	let adapters = instance.enumerate_adapters(backends);
+	let adapter = match selector {
+		AdapterSelector::PowerPreference(_) => None,
+		AdapterSelector::Index(index) => adapters.into_iter().nth(*index),
+		AdapterSelector::NamePattern(pattern) => {
+			let pattern = pattern.to_lowercase();
+			adapters
+				.into_iter()
+				.find(|adapter| adapter.get_info().name.to_lowercase().contains(&pattern))
+		}
+	}?;
+
+	let compatible = compatible_surface.is_none_or(|surface| adapter.is_surface_supported(surface));
+	if !compatible {
+		log::warn!("{} can't present to the window", adapter.get_info().name);
+	}
+	compatible.then_some(adapter)
+}
+
+// The browser only hands out the adapter it picks
+#[cfg(target_arch = "wasm32")]
+fn find_adapter(
+	_instance: &wgpu::Instance,
+	_backends: wgpu::Backends,
+	_compatible_surface: Option<&wgpu::Surface>,
+	_selector: &AdapterSelector,
+) -> Option<wgpu::Adapter> {
+	None
+}
+
+async fn finish_request_device(
+	adapter: wgpu::Adapter,
+	state_config: StateConfig,
+	progress: &mut dyn FnMut(InitPhase),
+) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), InitError> {
+
+	// GPU frame timing is used whenever the adapter can do it, and the
+	// compressed texture formats are enabled for Texture::from_compressed
+	let optional_features = adapter.features() & (
+		wgpu::Features::TIMESTAMP_QUERY
+			| wgpu::Features::TEXTURE_COMPRESSION_BC
+			| wgpu::Features::TEXTURE_COMPRESSION_ETC2
+			| wgpu::Features::TEXTURE_COMPRESSION_ASTC_LDR
+	);
+
+	progress(InitPhase::RequestingDevice);
+	let (device, queue) = adapter.request_device(
+		&wgpu::DeviceDescriptor {
+			features: state_config.features | optional_features,
+			limits: state_config.limits,
+			label: None
+		},
+		None,	// Trace path
+	).await.map_err(InitError::RequestDevice)?;
+
+	Ok((adapter, device, queue))
+}
+
+// wgpu 0.13 has no device-lost callback. A lost device instead fails
+// whatever touches it next, and those failures reach the uncaptured error
+// handler as validation errors caused by "parent device is lost".
+//
+// In practice only Vulkan and DX12 report this, when the driver resets the
+// GPU (a TDR on Windows, a hang, a driver update) or an external GPU is
+// unplugged. Metal keeps the device usable in almost every such case,
+// and GL and WebGL/WebGPU on wgpu 0.13 don't surface a loss at all.
+// A loss noticed by queue.submit itself is still a panic inside wgpu.
+fn is_device_lost(error: &wgpu::Error) -> bool {
+	let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+	while let Some(error) = source {
+		if error.to_string() == "parent device is lost" {
+			return true;
+		}
+		source = error.source();
+	}
+	false
+}
+
+// Sets the returned flag once the device is lost, see is_device_lost. Any
+// other error is a bug and panics, like wgpu's default handler.
+fn watch_device_lost(device: &wgpu::Device) -> Arc<AtomicBool> {
+	let device_lost = Arc::new(AtomicBool::new(false));
+	let flag = device_lost.clone();
+	device.on_uncaptured_error(move |error| {
+		if is_device_lost(&error) {
+			if !flag.swap(true, Ordering::Relaxed) {
+				log::error!("GPU device lost: {}", error);
+			}
+		} else {
+			panic!("wgpu error: {}", error);
+		}
+	});
+	device_lost
+}
+
+// Depth tests are written for near = 0 and far = 1, this flips them
+// around when the camera uses reverse-Z (see Camera::reverse_z)
+fn depth_compare(compare: wgpu::CompareFunction, reverse_z: bool) -> wgpu::CompareFunction {
+	use wgpu::CompareFunction::*;
+
+	match (compare, reverse_z) {
+		(Less, true) => Greater,
+		(LessEqual, true) => GreaterEqual,
+		(Greater, true) => Less,
+		(GreaterEqual, true) => LessEqual,
+		(compare, _) => compare,
+	}
+}
+
+// What the depth buffer is cleared to, the far plane's depth
+fn depth_clear_value(reverse_z: bool) -> f32 {
+	if reverse_z { 0.0 } else { 1.0 }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+	device: &wgpu::Device,
+	layout: &wgpu::PipelineLayout,
+	color_format: wgpu::TextureFormat,
+	sample_count: u32,
+	polygon_mode: wgpu::PolygonMode,
+	topology: wgpu::PrimitiveTopology,
+	shader: &wgpu::ShaderModule,
+	vertex_layout: wgpu::VertexBufferLayout,
+	reverse_z: bool,
+	stencil: &wgpu::StencilState,
+) -> wgpu::RenderPipeline {
+	create_render_pipeline_with_entry_points(device, layout, color_format, sample_count, polygon_mode, topology, shader, "vs_main", "fs_main", vertex_layout, reverse_z, stencil)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline_with_entry_points(
+	device: &wgpu::Device,
+	layout: &wgpu::PipelineLayout,
+	color_format: wgpu::TextureFormat,
+	sample_count: u32,
+	polygon_mode: wgpu::PolygonMode,
+	topology: wgpu::PrimitiveTopology,
+	shader: &wgpu::ShaderModule,
+	vs_entry_point: &str,
+	fs_entry_point: &str,
+	vertex_layout: wgpu::VertexBufferLayout,
+	reverse_z: bool,
+	stencil: &wgpu::StencilState,
+) -> wgpu::RenderPipeline {
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some("Render Pipeline"),
+		layout: Some(layout),
+		vertex: wgpu::VertexState {
+			module: shader,
+			entry_point: vs_entry_point, // Vertex shader entry point function
+			buffers: &[ // Vertex buffers
+				vertex_layout,
+				InstanceRaw::desc(),
+			],
+		},
+		fragment: Some(wgpu::FragmentState {
+			module: shader,
+			entry_point: fs_entry_point, // Fragment shader entry point function
+			targets: &[Some(wgpu::ColorTargetState { // Output information
+				format: color_format,
+				// REPLACE writes the shader output as is. On an sRGB target that
+				// output is treated as linear and encoded on write; if blending
+				// is ever enabled it also happens in linear space there.
+				blend: Some(wgpu::BlendState::REPLACE),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		primitive: wgpu::PrimitiveState {
+			// TriangleList: every three vertices correspond to one triangle
+			topology,
+			strip_index_format: None,
+			front_face: wgpu::FrontFace::Ccw,
+			cull_mode: Some(wgpu::Face::Back),
+			// Line requires Features::POLYGON_MODE_LINE, Point requires Features::POLYGON_MODE_POINT
+			polygon_mode,
+			// Requires Features::DEPTH_CLIP_CONTROL
+			unclipped_depth: false,
+			// Requires Features::CONSERVATIVE_RASTERIZATION
+			conservative: false,
+		},
+
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: texture::Texture::DEPTH_FORMAT,
+			depth_write_enabled: true,
+			// Draw fragments that are at least as close as what's already there
+			depth_compare: depth_compare(wgpu::CompareFunction::LessEqual, reverse_z),
+			stencil: stencil.clone(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState {
+			count: sample_count,
+			mask: !0, // Use all samples
+			alpha_to_coverage_enabled: false,
+		},
+		multiview: None,
+	})
+}
+
+fn supports_sample_count(
+	color_features: wgpu::TextureFormatFeatureFlags,
+	depth_features: wgpu::TextureFormatFeatureFlags,
+	sample_count: u32,
+) -> bool {
+	match sample_count {
+		1 => true,
+		// The color target has to be resolvable and the depth texture
+		// has to be multisampled along with it.
+		2 | 4 | 8 | 16 => {
+			color_features.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+				&& depth_features.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE)
+		}
+		_ => false,
+	}
+}
+
+// Decodes one of the scene's textures, sampled the way `filter` says
+fn diffuse_texture_from_bytes(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str, filter: TextureFilter) -> anyhow::Result<texture::Texture> {
+	match filter {
+		TextureFilter::Linear => texture::Texture::from_bytes_anisotropic(device, queue, bytes, label, texture::Texture::MAX_ANISOTROPY),
+		TextureFilter::Nearest => texture::Texture::from_bytes_nearest(device, queue, bytes, label),
+	}
+}
+
+// Read the same way as the embedded default so it's filtered the same
+#[cfg(not(target_arch = "wasm32"))]
+fn load_diffuse_texture(device: &wgpu::Device, queue: &wgpu::Queue, path: &std::path::Path, filter: TextureFilter) -> anyhow::Result<texture::Texture> {
+	use anyhow::Context;
+
+	let bytes = std::fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+	let label = path.to_string_lossy();
+	diffuse_texture_from_bytes(device, queue, &bytes, &label, filter)
+}
+
+// There's no file system to load StateConfig::diffuse_texture from on the web
+#[cfg(target_arch = "wasm32")]
+fn load_diffuse_texture(_device: &wgpu::Device, _queue: &wgpu::Queue, _path: &std::path::Path, _filter: TextureFilter) -> anyhow::Result<texture::Texture> {
+	anyhow::bail!("Textures can't be loaded from files on the web")
+}
+
+// The layout has to match how the textures were created, see TextureFilter
+fn texture_bind_group_builder(texture: &texture::Texture, filter: TextureFilter) -> bind_group::BindGroupBuilder<'_> {
+	let mut builder = bind_group::BindGroupBuilder::new();
+	builder.label("diffuse_bind_group");
+	match filter {
+		TextureFilter::Linear => builder
+			.texture(0, wgpu::ShaderStages::FRAGMENT, &texture.view)
+			.sampler(1, wgpu::ShaderStages::FRAGMENT, &texture.sampler),
+		TextureFilter::Nearest => builder
+			.unfiltered_texture(0, wgpu::ShaderStages::FRAGMENT, &texture.view)
+			.non_filtering_sampler(1, wgpu::ShaderStages::FRAGMENT, &texture.sampler),
+	};
+	builder
+}
+
+// Cubemap faces for a simple sky, fading from the horizon up to the zenith
+// with a plain ground below. Real skies can be loaded with
+// texture::Texture::from_cubemap instead.
+fn sky_gradient() -> [image::DynamicImage; 6] {
+	const SIZE: u32 = 64;
+	const HORIZON: [f32; 3] = [200.0, 225.0, 250.0];
+	const ZENITH: [f32; 3] = [40.0, 100.0, 200.0];
+	const GROUND: [f32; 3] = [90.0, 80.0, 70.0];
+
+	// Maps a pixel on each face (+X, -X, +Y, -Y, +Z, -Z) to its direction
+	let face_direction = |face: usize, u: f32, v: f32| -> cgmath::Vector3<f32> {
+		match face {
+			0 => cgmath::Vector3::new(1.0, -v, -u),
+			1 => cgmath::Vector3::new(-1.0, -v, u),
+			2 => cgmath::Vector3::new(u, 1.0, v),
+			3 => cgmath::Vector3::new(u, -1.0, -v),
+			4 => cgmath::Vector3::new(u, -v, 1.0),
+			_ => cgmath::Vector3::new(-u, -v, -1.0),
+		}
+	};
+
+	[0, 1, 2, 3, 4, 5].map(|face| {
+		image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+			let u = (x as f32 + 0.5) / SIZE as f32 * 2.0 - 1.0;
+			let v = (y as f32 + 0.5) / SIZE as f32 * 2.0 - 1.0;
+			let height = face_direction(face, u, v).normalize().y;
+
+			let color = if height < 0.0 {
+				GROUND
+			} else {
+				let t = height.sqrt();
+				[0, 1, 2].map(|i| HORIZON[i] + (ZENITH[i] - HORIZON[i]) * t)
+			};
+			image::Rgba([color[0] as u8, color[1] as u8, color[2] as u8, 255])
+		}))
+	})
+}
+
+// A generated fallback texture so there is more than one to switch between
+fn checkerboard() -> image::DynamicImage {
+	const SIZE: u32 = 64;
+	const TILE: u32 = 8;
+
+	image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+		if (x / TILE + y / TILE).is_multiple_of(2) {
+			image::Rgba([255, 255, 255, 255])
+		} else {
+			image::Rgba([64, 64, 64, 255])
+		}
+	}))
+}
+
+// Green discs on a transparent background, a stand-in for foliage to try
+// State::set_alpha_cutoff on. The alpha fades out over the discs' edges, so
+// the cutoff decides how big they end up.
+fn cutout() -> image::DynamicImage {
+	const SIZE: u32 = 64;
+	const CELL: u32 = 16;
+
+	image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+		// Distance from the center of this pixel's cell
+		let center = CELL as f32 / 2.0;
+		let dx = (x % CELL) as f32 + 0.5 - center;
+		let dy = (y % CELL) as f32 + 0.5 - center;
+		let distance = (dx * dx + dy * dy).sqrt();
+
+		// Opaque up to 4 pixels from the center, transparent from 8
+		let alpha = ((8.0 - distance) / 4.0).clamp(0.0, 1.0);
+		image::Rgba([60, 160, 60, (alpha * 255.0) as u8])
+	}))
+}
+
+// Which GPU a State renders with, see StateConfig::adapter
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterSelector {
+	// Let wgpu pick, like State::new does with the default preference
+	PowerPreference(wgpu::PowerPreference),
+	// The index into instance.enumerate_adapters, the same order the
+	// adapters are listed in on every run
+	Index(usize),
+	// The first adapter whose name contains this, ignoring case, e.g. "nvidia"
+	NamePattern(String),
+}
+
+impl Default for AdapterSelector {
+	fn default() -> Self {
+		Self::PowerPreference(wgpu::PowerPreference::default())
+	}
+}
+
+// Lets the caller opt into GPU features and limits, and pick the adapter
+// and textures, when creating a State with State::new_with_config. Start
+// from the default and change what you need:
+//
+//     let config = StateConfig {
+//         features: wgpu::Features::POLYGON_MODE_LINE,
+//         ..Default::default()
+//     };
+#[derive(Clone, Debug)]
+pub struct StateConfig {
+	// Which graphics APIs wgpu may use, e.g. Backends::VULKAN or
+	// Backends::DX12 to reproduce a backend-specific bug without going
+	// through environment variables. Creating the State fails with
+	// NoAdapter when none of them are available.
+	pub backends: wgpu::Backends,
+	// Requested from the device, e.g. POLYGON_MODE_LINE for
+	// State::set_polygon_mode. Creating the State fails when the adapter
+	// doesn't support them.
+	pub features: wgpu::Features,
+	pub limits: wgpu::Limits,
+	// Which GPU to render with, including the power preference. When no
+	// adapter matches, or it can't draw to the window, the default one is
+	// used and a warning logged. Index and NamePattern only work natively,
+	// the web always gets the browser's adapter.
+	pub adapter: AdapterSelector,
+	// Textures the scene with this image instead of the embedded
+	// happy-tree.png, so the demo can show something else without
+	// recompiling. Creating the State fails with InitError::Texture when
+	// it can't be loaded, which is always the case on the web.
+	pub diffuse_texture: Option<std::path::PathBuf>,
+	// How the scene's textures are sampled. Nearest keeps pixel art from
+	// being blurred. Set at creation because the texture bind group
+	// layout, and every pipeline using it, depends on it.
+	pub texture_filter: TextureFilter,
+}
+
+impl Default for StateConfig {
+	fn default() -> Self {
+		Self {
+			// Vulkan + Metal + DX12 + Browser WebGPU, and GL/WebGL as a fallback
+			backends: wgpu::Backends::all(),
+			features: wgpu::Features::empty(),
+
+			// WebGL doesn't support all of wgpu's features, so if
+			// we're building for the web we'll have to disable some.
+			limits: if cfg!(target_arch = "wasm32") {
+				wgpu::Limits::downlevel_webgl2_defaults()
+			} else {
+				wgpu::Limits::default()
+			},
+			adapter: AdapterSelector::default(),
+			diffuse_texture: None,
+			texture_filter: TextureFilter::default(),
+		}
+	}
+}
+
+// GPU objects and scene data that don't depend on a window or its size.
+// Everything is reference counted so several windows can draw the same
+// scene with one device and queue.
+struct SharedResources {
+	instance: Rc<wgpu::Instance>,
+	adapter: Rc<wgpu::Adapter>,
+	device: Rc<wgpu::Device>,
+	queue: Rc<wgpu::Queue>,
+	shader: Rc<wgpu::ShaderModule>,
+	color_shader: Rc<wgpu::ShaderModule>,
+	textures: Rc<Vec<texture::Texture>>,
+	texture_bind_group_layout: Rc<wgpu::BindGroupLayout>,
+	// What the device and textures were created from
+	state_config: StateConfig,
+	bind_groups: Rc<Vec<wgpu::BindGroup>>,
+	sky: Rc<texture::Texture>,
+	meshes: Rc<RefCell<Vec<mesh::Mesh>>>,
+	// Drawn instead of meshes when vertex colors are on
+	color_meshes: Rc<RefCell<Vec<mesh::Mesh>>>,
+	instances: Rc<Vec<Instance>>,
+	instance_buffer: Rc<wgpu::Buffer>,
+	// Set by the device's error handler, see watch_device_lost
+	device_lost: Arc<AtomicBool>,
+}
+
+impl SharedResources {
+	fn new(
+		instance: wgpu::Instance,
+		adapter: wgpu::Adapter,
+		device: wgpu::Device,
+		queue: wgpu::Queue,
+		state_config: StateConfig,
+	) -> Result<Self, InitError> {
+		let device_lost = watch_device_lost(&device);
+		let texture_filter = state_config.texture_filter;
+
+		let sky = texture::Texture::from_cubemap_images(&device, &queue, &sky_gradient(), Some("sky_cubemap"))
+			.map_err(InitError::Texture)?;
+
+		let diffuse = match state_config.diffuse_texture.as_deref() {
+			Some(path) => load_diffuse_texture(&device, &queue, path, texture_filter),
+			None => diffuse_texture_from_bytes(&device, &queue, include_bytes!("happy-tree.png"), "happy_tree_texture", texture_filter),
+		};
+		let checkerboard = match texture_filter {
+			TextureFilter::Linear => texture::Texture::from_image(&device, &queue, &checkerboard(), Some("checkerboard_texture")),
+			TextureFilter::Nearest => texture::Texture::from_image_nearest(&device, &queue, &checkerboard(), Some("checkerboard_texture")),
+		};
+		let cutout = match texture_filter {
+			TextureFilter::Linear => texture::Texture::from_image(&device, &queue, &cutout(), Some("cutout_texture")),
+			TextureFilter::Nearest => texture::Texture::from_image_nearest(&device, &queue, &cutout(), Some("cutout_texture")),
+		};
+		let textures = vec![
+			diffuse.map_err(InitError::Texture)?,
+			checkerboard.map_err(InitError::Texture)?,
+			cutout.map_err(InitError::Texture)?,
+		];
+
+		// Every texture gets its own bind group, all sharing one layout
+		let texture_bind_group_layout = texture_bind_group_builder(&textures[0], texture_filter).build_layout(&device);
+		let bind_groups = textures
+			.iter()
+			.map(|texture| texture_bind_group_builder(texture, texture_filter).build_bind_group(&device, &texture_bind_group_layout))
+			.collect();
+
+		#[cfg(not(feature = "hot-reload"))]
+		let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+		// Read the shader from disk so edits show up without rebuilding
+		#[cfg(feature = "hot-reload")]
+		let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("shader.wgsl"),
+			source: wgpu::ShaderSource::Wgsl(hot_reload::read_shader().into()),
+		});
+
+		let color_shader = device.create_shader_module(wgpu::include_wgsl!("shader_color.wgsl"));
+
+		// Dynamic so update_vertices can animate it
+		let meshes = vec![mesh::Mesh::from_vertices_dynamic(&device, VERTICES, INDICES, "Pentagon")];
+		let color_meshes = vec![mesh::Mesh::from_vertices_with_label(&device, COLORED_VERTICES, INDICES, "Colored Pentagon")];
+
+		let instances = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
+			(0..NUM_INSTANCES_PER_ROW).map(move |x| {
+				let position = cgmath::Vector3 { x: x as f32, y: 0.0, z: z as f32 } - INSTANCE_DISPLACEMENT;
+
+				let rotation = if position.is_zero() {
+					// This is needed so an object at (0, 0, 0) won't get scaled to zero
+					// as Quaternions can effect scale if they're not created correctly
+					cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
+				} else {
+					cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+				};
+
+				Instance { position, rotation }
+			})
+		}).collect::<Vec<_>>();
+
+		let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+		let instance_buffer = device.create_buffer_init(
+			&wgpu::util::BufferInitDescriptor {
+				label: Some("Instance Buffer"),
+				contents: bytemuck::cast_slice(&instance_data),
+				usage: wgpu::BufferUsages::VERTEX,
+			}
+		);
+
+		Ok(Self {
+			instance: Rc::new(instance),
+			adapter: Rc::new(adapter),
+			device: Rc::new(device),
+			queue: Rc::new(queue),
+			shader: Rc::new(shader),
+			color_shader: Rc::new(color_shader),
+			textures: Rc::new(textures),
+			texture_bind_group_layout: Rc::new(texture_bind_group_layout),
+			state_config,
+			bind_groups: Rc::new(bind_groups),
+			sky: Rc::new(sky),
+			meshes: Rc::new(RefCell::new(meshes)),
+			color_meshes: Rc::new(RefCell::new(color_meshes)),
+			instances: Rc::new(instances),
+			instance_buffer: Rc::new(instance_buffer),
+			device_lost,
+		})
+	}
+}
+
+pub struct State {
+	target: RenderTarget,
+	// The Rc fields are shared with other windows' States, see new_sharing
+	instance: Rc<wgpu::Instance>,
+	adapter: Rc<wgpu::Adapter>,
+	adapter_info: wgpu::AdapterInfo,
+	device: Rc<wgpu::Device>,
+	queue: Rc<wgpu::Queue>,
+	config: wgpu::SurfaceConfiguration,
+	size: winit::dpi::PhysicalSize<u32>,
+	// Physical pixels per logical pixel of the window, 1.0 when headless
+	scale_factor: f64,
+	// Set while the window is resized to 0x0, which is how minimizing shows
+	// up. size keeps the last real size so the surface can be restored.
+	minimized: bool,
+	clear_color: wgpu::Color,
+	// When set, moving the cursor over the window changes the clear color
+	clear_color_follows_cursor: bool,
+	// When false the previous frame is drawn over instead of cleared
+	clear_enabled: bool,
+	shader: Rc<wgpu::ShaderModule>,
+	render_pipeline_layout: wgpu::PipelineLayout,
+	render_pipeline: wgpu::RenderPipeline,
+	polygon_mode: wgpu::PolygonMode,
+	// TriangleList, or PointList to draw only the vertices, see set_topology
+	topology: wgpu::PrimitiveTopology,
+	// Stencil test and writes of the mesh pipelines, see set_stencil
+	stencil: wgpu::StencilState,
+	stencil_reference: u32,
+	meshes: Rc<RefCell<Vec<mesh::Mesh>>>,
+	// Draws color_meshes from their vertex colors, without the texture,
+	// instead of render_pipeline and meshes when use_vertex_colors is set
+	color_shader: Rc<wgpu::ShaderModule>,
+	color_pipeline_layout: wgpu::PipelineLayout,
+	color_pipeline: wgpu::RenderPipeline,
+	color_meshes: Rc<RefCell<Vec<mesh::Mesh>>>,
+	use_vertex_colors: bool,
+	// Draws meshes in a solid color with only the camera bound, instead of
+	// render_pipeline, when untextured is set. See set_untextured.
+	untextured_shader: Rc<wgpu::ShaderModule>,
+	untextured_pipeline_layout: wgpu::PipelineLayout,
+	untextured_pipeline: wgpu::RenderPipeline,
+	untextured: bool,
+	textures: Rc<Vec<texture::Texture>>,
+	// Shared by every texture's bind group. The camera and light layouts
+	// live on their Uniforms.
+	texture_bind_group_layout: Rc<wgpu::BindGroupLayout>,
+	// What texture_bind_group_layout was created for, materials have to match
+	texture_filter: TextureFilter,
+	// Kept to create the State again after the device is lost
+	state_config: StateConfig,
+	// One bind group per texture, in the same order
+	bind_groups: Rc<Vec<wgpu::BindGroup>>,
+	active_texture: usize,
+	camera: camera::Camera,
+	camera_controller: camera::CameraController,
+	fps_controller: camera::FpsController,
+	orbit_controller: camera::OrbitController,
+	camera_mode: CameraMode,
+	frame_timer: frame_timer::FrameTimer,
+	// None when the device doesn't support timestamp queries
+	gpu_timer: Option<gpu_timer::GpuTimer>,
+	// One slot per camera, picked with a dynamic offset. The scene is seen
+	// through CAMERA_SLOT.
+	camera_uniform: uniform::UniformArray<camera::CameraUniform>,
+	// The camera changes every frame, so rather than queue.write_buffer
+	// (which may allocate fresh staging memory on each call) it is uploaded
+	// through this belt. The belt keeps its chunks around and reuses them
+	// once recalled, trading a little memory held between frames for fewer
+	// allocations.
+	staging_belt: wgpu::util::StagingBelt,
+	light_uniform: uniform::Uniform<light::LightUniform>,
+	texture_transform: uniform::Uniform<texture::TextureTransform>,
+	shading: uniform::Uniform<light::ShadingUniform>,
+	// The sum of every step's dt, so it's the time since the State was
+	// created, or the simulated time when stepping with a fixed dt
+	time: std::time::Duration,
+	time_uniform: uniform::Uniform<frame_timer::TimeUniform>,
+	// The texture transform, shading parameters, sky cubemap and time the
+	// meshes are drawn with, bound at group 3
+	shading_bind_group: wgpu::BindGroup,
+	// The MSAA color and depth targets the scene is drawn into
+	framebuffer: framebuffer::Framebuffer,
+	// The scene is rendered into this first, then post_process draws it
+	// to the surface
+	scene_texture: texture::Texture,
+	post_process: post_process::PostProcess,
+	sky: Rc<texture::Texture>,
+	skybox: skybox::Skybox,
+	// Draw the skybox instead of clearing to clear_color
+	show_skybox: bool,
+	debug_lines: debug_lines::DebugLines,
+	// Whether the world axes and the instances' bounding boxes are drawn
+	show_debug_lines: bool,
+	// None when the device can't run compute shaders, e.g. on WebGL
+	particles: Option<particles::Particles>,
+	picker: picking::Picker,
+	depth_capture: depth_capture::DepthCapture,
+	text_renderer: text::TextRenderer,
+	sprite_batch: sprite::SpriteBatch,
+	// Extra targets the textured meshes are drawn into, see set_render_targets
+	render_targets: Option<render_targets::RenderTargets>,
+	// Run in order by render, see render_stage
+	stages: Vec<Box<dyn render_stage::RenderPassStage>>,
+	// Whether the FPS / frame time counter is drawn over the scene
+	show_overlay: bool,
+	// Per mesh, whether any of its instances is in the camera's frustum.
+	// Meshes added since the last update are missing and get drawn.
+	visible_meshes: Vec<bool>,
+	cull_stats: CullStats,
+	color_format_features: wgpu::TextureFormatFeatureFlags,
+	depth_format_features: wgpu::TextureFormatFeatureFlags,
+	supported_present_modes: Vec<wgpu::PresentMode>,
+	instances: Rc<Vec<Instance>>,
+	instance_buffer: Rc<wgpu::Buffer>,
+	device_lost: Arc<AtomicBool>,
+	#[cfg(feature = "hot-reload")]
+	shader_watcher: hot_reload::ShaderWatcher,
+}
+
+impl State {
+	// Creating some of the wgpu types requires async code
+	pub async fn new(window: &Window) -> Result<Self, InitError> {
+		Self::new_with_progress(window, |_| {}).await
+	}
+
+	// Like new, but calls `progress` as each step of the initialization
+	// starts, e.g. to update a loading screen while the web target waits
+	// on the browser.
+	pub async fn new_with_progress(window: &Window, mut progress: impl FnMut(InitPhase)) -> Result<Self, InitError> {
+		Self::create(window, StateConfig::default(), &mut progress).await
+	}
+
+	// Like new, but with the features, limits, adapter and textures from
+	// `state_config`
+	pub async fn new_with_config(window: &Window, state_config: StateConfig) -> Result<Self, InitError> {
+		Self::create(window, state_config, &mut |_| {}).await
+	}
+
+	async fn create(
+		window: &Window,
+		state_config: StateConfig,
+		progress: &mut dyn FnMut(InitPhase),
+	) -> Result<Self, InitError> {
+		// The instance is a handle to our GPU
+		let instance = wgpu::Instance::new(state_config.backends);
+		let surface = unsafe { instance.create_surface(window) };
+		let (adapter, device, queue) = request_device(&instance, Some(&surface), state_config.clone(), progress).await?;
+
+		progress(InitPhase::LoadingTextures);
+		let shared = SharedResources::new(instance, adapter, device, queue, state_config)?;
+
+		progress(InitPhase::BuildingPipeline);
+		let state = Self::from_surface(shared, surface, window)?;
+
+		progress(InitPhase::Done);
+		Ok(state)
+	}
+
+	// Creates the state for another window that draws the same scene as
+	// `other`, reusing its device, queue, textures and buffers. The camera,
+	// surface and everything tied to the window's size are its own.
+	pub fn new_sharing(window: &Window, other: &State) -> Result<Self, InitError> {
+		let shared = other.shared();
+		let surface = unsafe { shared.instance.create_surface(window) };
+		Self::from_surface(shared, surface, window)
+	}
+
+	// Renders into an offscreen texture instead of a window, e.g. for
+	// comparing frames against golden images in CI. `format` has to be one
+	// of CAPTURE_FORMATS, so capture_frame can read the frames back.
+	pub async fn new_headless(width: u32, height: u32, format: wgpu::TextureFormat) -> Result<Self, InitError> {
+		if !CAPTURE_FORMATS.contains(&format) {
+			return Err(InitError::UnsupportedFormat(format));
+		}
+
+		let state_config = StateConfig::default();
+		let instance = wgpu::Instance::new(state_config.backends);
+		// Without a surface any adapter will do
+		let (adapter, device, queue) = request_device(&instance, None, state_config.clone(), &mut |_| {}).await?;
+		let shared = SharedResources::new(instance, adapter, device, queue, state_config)?;
+
+		// Nothing gets presented, but the config still describes the target
+		let config = wgpu::SurfaceConfiguration {
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+			format,
+			width,
+			height,
+			present_mode: wgpu::PresentMode::Fifo,
+		};
+		let target = RenderTarget::Offscreen(create_offscreen_texture(&shared.device, &config));
+
+		Self::from_shared(shared, target, config, vec![wgpu::PresentMode::Fifo])
+	}
+
+	fn from_surface(
+		shared: SharedResources,
+		surface: wgpu::Surface,
+		window: &Window,
+	) -> Result<Self, InitError> {
+		let size = window.inner_size();
+
+		// The shader works in linear space (textures are Rgba8UnormSrgb, so
+		// sampling decodes them), so prefer a surface that encodes back to
+		// sRGB on write. Otherwise the output looks too dark.
+		let supported_formats = surface.get_supported_formats(&shared.adapter);
+		let format = supported_formats
+			.iter()
+			.copied()
+			.find(|format| format.describe().srgb)
+			.or_else(|| supported_formats.first().copied())
+			.ok_or(InitError::NoSupportedFormat)?;
+		if !format.describe().srgb {
+			log::warn!("No sRGB surface format available, using {:?}", format);
+		}
+
+		let config = wgpu::SurfaceConfiguration {
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+			format,
+			width: size.width,
+			height: size.height,
+			present_mode: wgpu::PresentMode::Fifo,
+		};
+		surface.configure(&shared.device, &config);
+
+		// Cached so the present mode can be validated after the adapter is gone
+		let supported_present_modes = surface.get_supported_modes(&shared.adapter);
+
+		let mut state = Self::from_shared(shared, RenderTarget::Surface(surface), config, supported_present_modes)?;
+		state.scale_factor = window.scale_factor();
+		Ok(state)
+	}
+
+	// Everything past picking the adapter and render target is the same
+	// with and without a window.
+	fn from_shared(
+		shared: SharedResources,
+		target: RenderTarget,
+		config: wgpu::SurfaceConfiguration,
+		supported_present_modes: Vec<wgpu::PresentMode>,
+	) -> Result<Self, InitError> {
+		let SharedResources {
+			instance,
+			adapter,
+			device,
+			queue,
+			shader,
+			color_shader,
+			textures,
+			texture_bind_group_layout,
+			state_config,
+			bind_groups,
+			sky,
+			meshes,
+			color_meshes,
+			instances,
+			instance_buffer,
+			device_lost,
+		} = shared;
+		let texture_filter = state_config.texture_filter;
+
+		let size = winit::dpi::PhysicalSize::new(config.width, config.height);
+
+		let adapter_info = adapter.get_info();
+		log::info!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
+
+		// Use 4x MSAA when the adapter can multisample and resolve the surface format
+		let color_format_features = adapter.get_texture_format_features(config.format).flags;
+		let depth_format_features = adapter.get_texture_format_features(texture::Texture::DEPTH_FORMAT).flags;
+		let sample_count = if supports_sample_count(color_format_features, depth_format_features, 4) { 4 } else { 1 };
+		let framebuffer = framebuffer::Framebuffer::new(&device, &config, sample_count)
+			.expect("4 and 1 are valid sample counts");
+
+		let scene_texture = texture::Texture::create_render_target(&device, &config, "scene_texture");
+		let post_process = post_process::PostProcess::new(&device, config.format, &scene_texture);
+
+		let text_renderer = text::TextRenderer::new(&device, config.format);
+		let sprite_batch = sprite::SpriteBatch::new(&device, &texture_bind_group_layout, config.format);
+		let gpu_timer = gpu_timer::GpuTimer::new(&device, &queue);
+
+		let camera = camera::Camera {
+			// position the camera one unit up and 2 units back
+			// +z is out of the screen
+			eye: (0.0, 1.0, 2.0).into(),
+			// have it look at the origin
+			target: (0.0, 0.0, 0.0).into(),
+			// which way is "up"
+			up: cgmath::Vector3::unit_y(),
+			aspect: config.width as f32 / config.height as f32,
+			projection: camera::Projection::Perspective {
+				fovy: 45.0,
+				znear: 0.1,
+				zfar: 100.0,
+			},
+			coordinate_system: camera::CoordinateSystem::default(),
+			reverse_z: false,
+		};
+
+		let skybox = skybox::Skybox::new(&device, &sky, &camera, config.format, sample_count);
+
+		let camera_controller = camera::CameraController::new(0.2);
+		let fps_controller = camera::FpsController::new(4.0, 0.004);
+		let orbit_controller = camera::OrbitController::new(0.005, 0.1);
+	
+		// The uniforms all have the same layout, this makes them share one
+		let mut layout_cache = bind_group::LayoutCache::new();
+
+		let mut camera_raw = camera::CameraUniform::new();
+		camera_raw.update_view_proj(&camera);
+		let mut camera_uniform = uniform::UniformArray::new_cached(&device, &mut layout_cache, 1);
+		camera_uniform.push(camera_raw);
+		camera_uniform.upload(&device, &queue);
+		let debug_lines = debug_lines::DebugLines::new(&device, camera_uniform.layout(), config.format, sample_count);
+		let picker = picking::Picker::new(&device, camera_uniform.layout(), &config);
+		let depth_capture = depth_capture::DepthCapture::new(&device);
+		let particles = particles::Particles::is_supported(&adapter, &device)
+			.then(|| particles::Particles::new(&device, camera_uniform.layout(), config.format, sample_count));
+
+		let light_uniform = uniform::Uniform::new_cached(
+			&device,
+			&mut layout_cache,
+			light::LightUniform::new(cgmath::Vector3::new(-0.5, -1.0, -1.0), [1.0, 1.0, 1.0]),
+		);
+
+		let texture_transform = uniform::Uniform::new_cached(&device, &mut layout_cache, texture::TextureTransform::default());
+		let shading = uniform::Uniform::new(&device, light::ShadingUniform::default());
+		let time_uniform = uniform::Uniform::new(&device, frame_timer::TimeUniform::new(0.0));
+
+		// Group 3 of the mesh pipeline. The shading parameters, sky and time
+		// share it with the texture transform, a fifth group would go past the default
+		// max_bind_groups of 4.
+		let (shading_bind_group_layout, shading_bind_group) = bind_group::BindGroupBuilder::new()
+			.label("shading_bind_group")
+			.uniform(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT, texture_transform.buffer())
+			.uniform(1, wgpu::ShaderStages::FRAGMENT, shading.buffer())
+			.cube_texture(2, wgpu::ShaderStages::FRAGMENT, &sky.view)
+			.sampler(3, wgpu::ShaderStages::FRAGMENT, &sky.sampler)
+			.uniform(4, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT, time_uniform.buffer())
+			.build(&device);
+
+		let render_pipeline_layout =
+			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some("Render Pipeline Layout"),
+				bind_group_layouts: &[
+					&texture_bind_group_layout,
+					camera_uniform.layout(),
+					light_uniform.layout(),
+					&shading_bind_group_layout,
+				],
+				push_constant_ranges: &[],
+			}
+		);
+
+		let render_pipeline = create_render_pipeline(&device, &render_pipeline_layout, config.format, sample_count, wgpu::PolygonMode::Fill, wgpu::PrimitiveTopology::TriangleList, &shader, Vertex::desc(), camera.reverse_z, &wgpu::StencilState::default());
+
+		// No texture or texture transform, so camera and light move up to groups 0 and 1
+		let color_pipeline_layout =
+			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some("Color Pipeline Layout"),
+				bind_group_layouts: &[
+					camera_uniform.layout(),
+					light_uniform.layout(),
+				],
+				push_constant_ranges: &[],
+			}
+		);
+
+		let color_pipeline = create_render_pipeline(&device, &color_pipeline_layout, config.format, sample_count, wgpu::PolygonMode::Fill, wgpu::PrimitiveTopology::TriangleList, &color_shader, ColorVertex::desc(), camera.reverse_z, &wgpu::StencilState::default());
+
+		// Only the camera, so the untextured entry points of shader.wgsl find it at group 0
+		let untextured_pipeline_layout =
+			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some("Untextured Pipeline Layout"),
+				bind_group_layouts: &[
+					camera_uniform.layout(),
+				],
+				push_constant_ranges: &[],
+			}
+		);
+
+		// Built from the shader the State starts with, so replacing the
+		// shader with set_shader or hot reloading doesn't affect it
+		let untextured_shader = shader.clone();
+		let untextured_pipeline = create_render_pipeline_with_entry_points(&device, &untextured_pipeline_layout, config.format, sample_count, wgpu::PolygonMode::Fill, wgpu::PrimitiveTopology::TriangleList, &untextured_shader, "vs_untextured", "fs_untextured", Vertex::desc(), camera.reverse_z, &wgpu::StencilState::default());
+
+		Ok(Self {
+			target,
+			instance,
+			adapter,
+			adapter_info,
+			device,
+			queue,
+			config,
+			size,
+			scale_factor: 1.0,
+			minimized: false,
+			clear_color: wgpu::Color::WHITE,
+			clear_color_follows_cursor: false,
+			clear_enabled: true,
+			shader,
+			render_pipeline_layout,
+			render_pipeline,
+			polygon_mode: wgpu::PolygonMode::Fill,
+			topology: wgpu::PrimitiveTopology::TriangleList,
+			stencil: wgpu::StencilState::default(),
+			stencil_reference: 0,
+			meshes,
+			color_shader,
+			color_pipeline_layout,
+			color_pipeline,
+			color_meshes,
+			use_vertex_colors: false,
+			untextured_shader,
+			untextured_pipeline_layout,
+			untextured_pipeline,
+			untextured: false,
+			textures,
+			texture_bind_group_layout,
+			texture_filter,
+			state_config,
+			bind_groups,
+			active_texture: 0,
+			camera,
+			camera_controller,
+			fps_controller,
+			orbit_controller,
+			camera_mode: CameraMode::default(),
+			frame_timer: frame_timer::FrameTimer::new(),
+			gpu_timer,
+			camera_uniform,
+			staging_belt: wgpu::util::StagingBelt::new(STAGING_BELT_CHUNK_SIZE),
+			light_uniform,
+			texture_transform,
+			shading,
+			time: std::time::Duration::ZERO,
+			time_uniform,
+			shading_bind_group,
+			framebuffer,
+			scene_texture,
+			post_process,
+			sky,
+			skybox,
+			show_skybox: false,
+			debug_lines,
+			show_debug_lines: false,
+			particles,
+			picker,
+			depth_capture,
+			text_renderer,
+			sprite_batch,
+			render_targets: None,
+			stages: render_stage::default_stages(),
+			show_overlay: true,
+			visible_meshes: Vec::new(),
+			cull_stats: CullStats::default(),
+			color_format_features,
+			depth_format_features,
+			supported_present_modes,
+			instances,
+			instance_buffer,
+			device_lost,
+			#[cfg(feature = "hot-reload")]
+			shader_watcher: hot_reload::ShaderWatcher::new(hot_reload::SHADER_PATH),
+		})
+	}
+
+	// Cloning the Rcs is all it takes to share these with another State
+	fn shared(&self) -> SharedResources {
+		SharedResources {
+			instance: self.instance.clone(),
+			adapter: self.adapter.clone(),
+			device: self.device.clone(),
+			queue: self.queue.clone(),
+			shader: self.shader.clone(),
+			color_shader: self.color_shader.clone(),
+			textures: self.textures.clone(),
+			texture_bind_group_layout: self.texture_bind_group_layout.clone(),
+			state_config: self.state_config.clone(),
+			bind_groups: self.bind_groups.clone(),
+			sky: self.sky.clone(),
+			meshes: self.meshes.clone(),
+			color_meshes: self.color_meshes.clone(),
+			instances: self.instances.clone(),
+			instance_buffer: self.instance_buffer.clone(),
+			device_lost: self.device_lost.clone(),
+		}
+	}
+
+	// Whether the device was lost, after which nothing drawn with this
+	// State shows up anymore. run() recreates it, see recreate_states.
+	pub fn device_lost(&self) -> bool {
+		self.device_lost.load(Ordering::Relaxed)
+	}
+
+	// For creating buffers, textures and the like on the same device the
+	// scene is drawn with, e.g. for a UI integration
+	pub fn device(&self) -> &wgpu::Device {
+		&self.device
+	}
+
+	pub fn queue(&self) -> &wgpu::Queue {
+		&self.queue
+	}
+
+	// The GPU and graphics API the State renders with
+	pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+		self.adapter_info.clone()
+	}
+
+	// The limits and features the device was actually created with, which
+	// can be less than what the adapter supports.
+	pub fn limits(&self) -> wgpu::Limits {
+		self.device.limits()
+	}
+
+	pub fn features(&self) -> wgpu::Features {
+		self.device.features()
+	}
+
+	// How long the GPU took to draw a recent frame. None until the first
+	// measurement arrives, or always when timestamp queries aren't supported.
+	pub fn last_gpu_time(&self) -> Option<std::time::Duration> {
+		self.gpu_timer.as_ref().and_then(gpu_timer::GpuTimer::last_time)
+	}
+
+	// Shoots `count` particles up from the middle of the scene, they fall
+	// back down and fade out over a few seconds. Logs a warning instead
+	// when the device has no compute shaders.
+	pub fn spawn_particles(&mut self, count: usize) {
+		match &mut self.particles {
+			Some(particles) => particles.spawn(&self.queue, cgmath::Point3::new(0.0, 0.5, 0.0), count),
+			None => log::warn!("Particles need compute shaders, which this device doesn't support"),
+		}
+	}
+
+	pub fn cull_stats(&self) -> CullStats {
+		self.cull_stats
+	}
+
+	fn is_mesh_visible(&self, index: usize) -> bool {
+		self.visible_meshes.get(index).copied().unwrap_or(true)
+	}
+
+	// The texture a textured mesh is drawn with: its material's, or the
+	// active texture when it has none
+	fn mesh_bind_group<'a>(&'a self, mesh: &'a mesh::Mesh) -> &'a wgpu::BindGroup {
+		match &mesh.material {
+			Some(material) => &material.bind_group,
+			None => &self.bind_groups[self.active_texture],
+		}
+	}
+
+	// Whether this is an *Srgb format tells you if colors written to the
+	// target get gamma encoded or are stored as is.
+	pub fn surface_format(&self) -> wgpu::TextureFormat {
+		self.config.format
+	}
+
+	pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+		self.size
+	}
+
+	pub fn scale_factor(&self) -> f64 {
+		self.scale_factor
+	}
+
+	// Whether the window was last resized to 0x0. There's no surface to draw
+	// to then, so the frame loop skips these States until they're restored.
+	pub fn is_minimized(&self) -> bool {
+		self.minimized
+	}
+
+	// A zero width or height (e.g. a minimized window) is ignored, there's
+	// nothing to draw into and the aspect ratio would divide by zero.
+	pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+		self.minimized = new_size.width == 0 || new_size.height == 0;
+		if !self.minimized {
+			self.size = new_size;
+			self.config.width = new_size.width;
+			self.config.height = new_size.height;
+			// Otherwise the scene stretches with the window. Written right
+			// away so a frame drawn before the next update already fits.
+			self.camera.aspect = new_size.width as f32 / new_size.height as f32;
+			let mut camera_raw = *self.camera_uniform.get(CAMERA_SLOT);
+			camera_raw.update_view_proj(&self.camera);
+			self.camera_uniform.set(CAMERA_SLOT, camera_raw);
+			self.camera_uniform.upload(&self.device, &self.queue);
+			self.skybox.update(&self.queue, &self.camera);
+			match &mut self.target {
+				RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+				RenderTarget::Offscreen(texture) => *texture = create_offscreen_texture(&self.device, &self.config),
+			}
+			self.framebuffer.resize(&self.device, new_size);
+			self.scene_texture = texture::Texture::create_render_target(&self.device, &self.config, "scene_texture");
+			self.post_process.set_source(&self.device, &self.scene_texture);
+			self.picker.resize(&self.device, &self.config);
+			if let Some(render_targets) = &mut self.render_targets {
+				render_targets.resize(&self.device, &self.config);
+			}
+		}
+	}
+
+	// The index of the instance drawn at (x, y), in physical pixels from the
+	// window's top left like WindowEvent::CursorMoved, or None for the
+	// background. Uses the camera from the last rendered frame, so it
+	// matches what's on screen. Waits for the GPU, see Picker::pick.
+	pub fn pick(&self, x: u32, y: u32) -> Option<u32> {
+		if !self.picker.contains(x, y) {
+			return None;
+		}
+
+		let meshes = if self.use_vertex_colors {
+			self.color_meshes.borrow()
+		} else {
+			self.meshes.borrow()
+		};
+
+		let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+			label: Some("Picking Encoder"),
+		});
+		{
+			let mut render_pass = self.picker.begin_pass(&mut encoder, self.camera_uniform.bind_group(), self.camera_offset(), self.use_vertex_colors, x, y);
+			render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+			for mesh in meshes.iter() {
+				mesh.draw_instanced(&mut render_pass, 0..self.instances.len() as _);
+			}
+		}
+
+		self.picker.read(&self.device, &self.queue, encoder, x, y)
+	}
+
+	// The textured, vertex colored and untextured pipelines, after a change
+	// to anything they're created with: the sample count, polygon mode,
+	// topology, stencil state, reverse-Z or shader
+	fn rebuild_mesh_pipelines(&mut self) {
+		let sample_count = self.framebuffer.sample_count();
+		self.render_pipeline = create_render_pipeline(&self.device, &self.render_pipeline_layout, self.config.format, sample_count, self.polygon_mode, self.topology, &self.shader, Vertex::desc(), self.camera.reverse_z, &self.stencil);
+		self.color_pipeline = create_render_pipeline(&self.device, &self.color_pipeline_layout, self.config.format, sample_count, self.polygon_mode, self.topology, &self.color_shader, ColorVertex::desc(), self.camera.reverse_z, &self.stencil);
+		self.untextured_pipeline = create_render_pipeline_with_entry_points(&self.device, &self.untextured_pipeline_layout, self.config.format, sample_count, self.polygon_mode, self.topology, &self.untextured_shader, "vs_untextured", "fs_untextured", Vertex::desc(), self.camera.reverse_z, &self.stencil);
+	}
+
+	// E.g. 4 for 4x MSAA, or 1 to turn it off. Counts the color and depth
+	// formats can't do are ignored with a warning.
+	pub fn set_sample_count(&mut self, sample_count: u32) {
+		if !supports_sample_count(self.color_format_features, self.depth_format_features, sample_count) {
+			log::warn!("Sample count {} is not supported", sample_count);
+			return;
+		}
+
+		self.framebuffer = match framebuffer::Framebuffer::new(&self.device, &self.config, sample_count) {
+			Ok(framebuffer) => framebuffer,
+			Err(e) => {
+				log::warn!("{}", e);
+				return;
+			}
+		};
+		self.rebuild_mesh_pipelines();
+		self.skybox.set_sample_count(&self.device, sample_count);
+		self.debug_lines.set_sample_count(&self.device, sample_count);
+		if let Some(particles) = &mut self.particles {
+			particles.set_sample_count(&self.device, sample_count);
+		}
+	}
+
+	// Line and Point need their features to be requested when the device is
+	// created, see StateConfig::features. Without them the mode is ignored
+	// with a warning.
+	pub fn set_polygon_mode(&mut self, mode: wgpu::PolygonMode) {
+		let required_feature = match mode {
+			wgpu::PolygonMode::Fill => wgpu::Features::empty(),
+			wgpu::PolygonMode::Line => wgpu::Features::POLYGON_MODE_LINE,
+			wgpu::PolygonMode::Point => wgpu::Features::POLYGON_MODE_POINT,
+		};
+		if !self.device.features().contains(required_feature) {
+			log::warn!("Polygon mode {:?} requires {:?}, which the device wasn't created with", mode, required_feature);
+			return;
+		}
+
+		self.polygon_mode = mode;
+		self.rebuild_mesh_pipelines();
+	}
+
+	// Draws the meshes as filled triangles (TriangleList) or as a cloud of
+	// their vertices (PointList), one pixel each. Points ignore the index
+	// buffer, so shared vertices are drawn once. Other topologies are
+	// ignored with a warning.
+	pub fn set_topology(&mut self, topology: wgpu::PrimitiveTopology) {
+		if !matches!(topology, wgpu::PrimitiveTopology::TriangleList | wgpu::PrimitiveTopology::PointList) {
+			log::warn!("Topology {:?} isn't supported, expected TriangleList or PointList", topology);
+			return;
+		}
+
+		self.topology = topology;
+		self.rebuild_mesh_pipelines();
+	}
+
+	// Sets how the meshes test against and write to the stencil buffer,
+	// which is cleared to 0 every frame. The reference value the compare
+	// function and Replace op use is set with set_stencil_reference. The
+	// debug lines and particles ignore the stencil buffer.
+	pub fn set_stencil(&mut self, stencil: wgpu::StencilState) {
+		self.stencil = stencil;
+		self.rebuild_mesh_pipelines();
+	}
+
+	pub fn set_stencil_reference(&mut self, reference: u32) {
+		self.stencil_reference = reference;
+	}
+
+	// Draws the meshes unlit in a solid color, with a pipeline whose only
+	// bind group is the camera, instead of textured. Doesn't affect the
+	// vertex colored meshes drawn when use_vertex_colors is set.
+	pub fn set_untextured(&mut self, untextured: bool) {
+		self.untextured = untextured;
+	}
+
+	// Switches the camera to reverse-Z and every depth tested pipeline and
+	// depth clear along with it, see Camera::reverse_z.
+	pub fn set_reverse_z(&mut self, reverse_z: bool) {
+		self.camera.reverse_z = reverse_z;
+		self.rebuild_mesh_pipelines();
+		self.debug_lines.set_reverse_z(&self.device, reverse_z);
+		if let Some(particles) = &mut self.particles {
+			particles.set_reverse_z(&self.device, reverse_z);
+		}
+		self.picker.set_reverse_z(&self.device, reverse_z);
+		if let Some(render_targets) = &mut self.render_targets {
+			render_targets.set_reverse_z(&self.device, &self.render_pipeline_layout, reverse_z);
+		}
+	}
+
+	// Also draws the textured meshes into one texture per format every frame,
+	// all in one pass after the scene, e.g. a G-buffer with GBUFFER_FORMATS
+	// and gbuffer_shader. `shader` gets the same bind groups and vertex
+	// inputs as shader.wgsl, and its fs_main has to write one @location
+	// output per format, in order. On an error, e.g. when the outputs don't
+	// line up with the formats, the current targets are kept.
+	pub fn set_render_targets(&mut self, formats: &[wgpu::TextureFormat], shader: wgpu::ShaderModuleDescriptor) -> anyhow::Result<()> {
+		self.render_targets = Some(render_targets::RenderTargets::new(
+			&self.device,
+			&self.config,
+			&self.render_pipeline_layout,
+			formats,
+			shader,
+			self.camera.reverse_z,
+		)?);
+		Ok(())
+	}
+
+	pub fn clear_render_targets(&mut self) {
+		self.render_targets = None;
+	}
+
+	// The texture render target number `index` was drawn into last frame, in
+	// the order of the formats given to set_render_targets
+	pub fn render_target_view(&self, index: usize) -> Option<&wgpu::TextureView> {
+		self.render_targets.as_ref()?.view(index)
+	}
+
+	// Replaces the shader the meshes are drawn with. It has to use the same
+	// bind groups and vertex inputs as shader.wgsl. If it doesn't compile,
+	// or doesn't fit the pipeline, the error is returned and the current
+	// shader is kept.
+	pub fn set_shader(&mut self, shader: wgpu::ShaderModuleDescriptor) -> Result<(), wgpu::Error> {
+		self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+		let shader = self.device.create_shader_module(shader);
+		let previous = std::mem::replace(&mut self.shader, Rc::new(shader));
+		self.rebuild_mesh_pipelines();
+
+		match pollster::block_on(self.device.pop_error_scope()) {
+			Some(e) => {
+				self.shader = previous;
+				self.rebuild_mesh_pipelines();
+				Err(e)
+			}
+			None => Ok(()),
+		}
+	}
+
+	// Replaces the shader used to draw the scene to the screen, e.g. for a
+	// blur effect. See post_process.wgsl for what it needs.
+	pub fn set_post_process_shader(&mut self, shader: wgpu::ShaderModuleDescriptor) -> Result<(), wgpu::Error> {
+		self.post_process.set_shader(&self.device, shader)
+	}
+
+	pub fn set_post_effect(&mut self, effect: post_process::PostEffect) {
+		self.post_process.set_effect(&self.queue, effect);
+	}
+
+	// Points the light along `direction`, in world space. It doesn't have
+	// to be unit length, it's normalized here.
+	pub fn set_light_direction(&mut self, direction: cgmath::Vector3<f32>) {
+		let light = *self.light_uniform.value();
+		self.light_uniform.update(&self.queue, light::LightUniform {
+			direction: light::LightUniform::new(direction, light.color).direction,
+			..light
+		});
+	}
+
+	// Remaps the movement keys of both camera controllers. Bound keys take
+	// precedence over the built-in shortcuts, e.g. binding Space to up
+	// stops it from spawning particles.
+	pub fn set_key_bindings(&mut self, bindings: KeyBindings) {
+		self.camera_controller.set_bindings(bindings.clone());
+		self.fps_controller.set_bindings(bindings);
+	}
+
+	// Units per frame the keyboard controller moves, at least 0.01. The
+	// +/- keys change it by a step.
+	pub fn set_camera_speed(&mut self, speed: f32) {
+		self.camera_controller.set_speed(speed);
+	}
+
+	// What the keyboard controller's speed is multiplied by while shift is held
+	pub fn set_camera_sprint_multiplier(&mut self, multiplier: f32) {
+		self.camera_controller.set_speed_multiplier(multiplier);
+	}
+
+	// Including sprinting, e.g. for an overlay
+	pub fn camera_speed(&self) -> f32 {
+		self.camera_controller.speed()
+	}
+
+	// Scales the lit color before it's written, e.g. 2.0 for a scene twice
+	// as bright. Most useful together with tone mapping, which keeps the
+	// brighter parts from clipping to white.
+	pub fn set_exposure(&mut self, exposure: f32) {
+		if !(exposure.is_finite() && exposure > 0.0) {
+			log::warn!("Exposure has to be positive, got {}", exposure);
+			return;
+		}
+
+		let light = *self.light_uniform.value();
+		self.light_uniform.update(&self.queue, light::LightUniform { exposure, ..light });
+	}
+
+	pub fn exposure(&self) -> f32 {
+		self.light_uniform.value().exposure
+	}
+
+	pub fn set_tone_mapping(&mut self, enabled: bool) {
+		let light = *self.light_uniform.value();
+		self.light_uniform.update(&self.queue, light::LightUniform { tone_mapping: enabled as u32, ..light });
+	}
+
+	// Blends the textured meshes toward a reflection of the sky cubemap:
+	// 0, the default, is only the lit texture and 1 a mirror. The
+	// reflection needs the meshes' normals (vertex location 2) and the sky
+	// bound at group 3, see shader.wgsl, so shaders passed to set_shader
+	// have to keep both to reflect. The sky is reflected whether or not
+	// it's drawn behind the scene.
+	pub fn set_reflectivity(&mut self, reflectivity: f32) {
+		if !(0.0..=1.0).contains(&reflectivity) {
+			log::warn!("Reflectivity has to be between 0 and 1, got {}", reflectivity);
+			return;
+		}
+
+		let mut shading = *self.shading.value();
+		shading.reflectivity = reflectivity;
+		self.shading.update(&self.queue, shading);
+	}
+
+	// Cuts out the parts of the textured meshes whose texture alpha is
+	// below `cutoff`, e.g. the gaps between leaves, instead of blending
+	// them. What's left is opaque and writes depth, so it doesn't need
+	// sorting. 0, the default, keeps everything. The cutout texture (the
+	// third one, key 3) and the Z key show it off.
+	pub fn set_alpha_cutoff(&mut self, cutoff: f32) {
+		if !(0.0..=1.0).contains(&cutoff) {
+			log::warn!("Alpha cutoff has to be between 0 and 1, got {}", cutoff);
+			return;
+		}
+
+		let mut shading = *self.shading.value();
+		shading.alpha_cutoff = cutoff;
+		self.shading.update(&self.queue, shading);
+	}
+
+	// Makes the textured meshes' brightness swing by `amplitude` every few
+	// seconds, driven by the time uniform. 0, the default, keeps it
+	// steady. The I key toggles a subtle pulse.
+	pub fn set_pulse(&mut self, amplitude: f32) {
+		if !(0.0..=1.0).contains(&amplitude) {
+			log::warn!("Pulse amplitude has to be between 0 and 1, got {}", amplitude);
+			return;
+		}
+
+		let mut shading = *self.shading.value();
+		shading.pulse = amplitude;
+		self.shading.update(&self.queue, shading);
+	}
+
+	// Replaces the scene's geometry, e.g. to switch models on a keypress.
+	// The old buffers are dropped with the old mesh. Like update_vertices
+	// this affects every window sharing the scene.
+	pub fn set_mesh(&mut self, vertices: &[Vertex], indices: &[u32]) {
+		*self.meshes.borrow_mut() = vec![mesh::Mesh::from_vertices_dynamic(&self.device, vertices, indices, "Mesh")];
+	}
+
+	// Like set_mesh, with the geometry of a Wavefront OBJ file, see
+	// model::parse_obj for what's read. The R key loads res/cube.obj.
+	pub fn load_obj<P: AsRef<std::path::Path>>(&mut self, path: P) -> anyhow::Result<()> {
+		let model = model::Model::load_obj(&self.device, path)?;
+		*self.meshes.borrow_mut() = vec![model.mesh];
+		Ok(())
+	}
+
+	// A material with the texture decoded from `bytes`, e.g. a PNG, sampled
+	// like the State's own textures. Give it to meshes with set_mesh_material.
+	pub fn create_material(&self, bytes: &[u8], label: &str) -> anyhow::Result<Rc<Material>> {
+		let texture = diffuse_texture_from_bytes(&self.device, &self.queue, bytes, label, self.texture_filter)?;
+		let material = material::MaterialBuilder::new()
+			.label(label)
+			.texture(texture)
+			.filter(self.texture_filter)
+			.build(&self.device, &self.texture_bind_group_layout);
+		Ok(Rc::new(material))
+	}
+
+	// Draws textured mesh number `index` with `material` instead of the
+	// active texture, or with the active texture again for None. The meshes
+	// are shared, so this affects every window sharing the scene.
+	pub fn set_mesh_material(&mut self, index: usize, material: Option<Rc<Material>>) {
+		match self.meshes.borrow_mut().get_mut(index) {
+			Some(mesh) => mesh.material = material,
+			None => log::warn!("There is no mesh {} to set the material of", index),
+		}
+	}
+
+	// Rewrites the first mesh's vertices, e.g. to animate it on the CPU. The
+	// mesh is shared, so every window sharing this State's scene sees it.
+	pub fn update_vertices(&mut self, vertices: &[Vertex]) {
+		self.meshes.borrow_mut()[0].update_vertices(&self.device, &self.queue, vertices);
+	}
+
+	// Texture coordinates become `tex_coords * scale + offset`, so a scale
+	// above 1 tiles the texture
+	pub fn set_uv_scale(&mut self, scale: [f32; 2], offset: [f32; 2]) {
+		self.texture_transform.update(&self.queue, texture::TextureTransform { scale, offset });
+	}
+
+	// Orthographic's left and right are scaled by the aspect ratio, see
+	// Projection. Takes effect from the next update.
+	pub fn set_projection(&mut self, projection: Projection) {
+		self.camera.projection = projection;
+	}
+
+	// For scenes authored in a left-handed space. The pipelines keep treating
+	// counter-clockwise as the front face, so under LeftHanded right-handed
+	// meshes like the built-in ones have their front faces culled. See
+	// CoordinateSystem.
+	pub fn set_coordinate_system(&mut self, coordinate_system: CoordinateSystem) {
+		self.camera.coordinate_system = coordinate_system;
+	}
+
+	// Orbit drags with the left mouse button and zooms with the wheel
+	pub fn set_camera_mode(&mut self, mode: CameraMode) {
+		self.camera_mode = mode;
+	}
+
+	pub fn set_clear_color(&mut self, color: wgpu::Color) {
+		self.clear_color = color;
+	}
+
+	// While on, moving the cursor across the window picks the clear color
+	pub fn set_clear_color_follows_cursor(&mut self, follows: bool) {
+		self.clear_color_follows_cursor = follows;
+	}
+
+	// Modes the surface doesn't support fall back to Fifo with a warning
+	pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) {
+		// Fifo is the only mode every surface is guaranteed to support
+		let mode = if self.supported_present_modes.contains(&mode) {
+			mode
+		} else {
+			log::warn!("Present mode {:?} is not supported, falling back to Fifo", mode);
+			wgpu::PresentMode::Fifo
+		};
+
+		self.config.present_mode = mode;
+		if let RenderTarget::Surface(surface) = &self.target {
+			surface.configure(&self.device, &self.config);
+		}
+	}
+
+	// Entry point for window events when driving State from your own event
+	// loop. Returns whether the event was consumed; anything else, like
+	// closing the window, is left to the caller.
+	pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+		if self.input(event) {
+			return true;
+		}
+
+		match event {
+			WindowEvent::Resized(physical_size) => {
+				self.resize(*physical_size);
+				true
+			}
+
+			WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+				self.scale_factor = *scale_factor;
+				// new_inner_size is &&mut so we have to dereference it twice
+				self.resize(**new_inner_size);
+				true
+			}
+
+			_ => false,
+		}
+	}
+
+	fn input(&mut self, event: &WindowEvent) -> bool {
+		// The active camera controller goes first, so keys given to
+		// set_key_bindings win over the shortcuts below
+		let consumed = match self.camera_mode {
+			CameraMode::Keyboard => self.camera_controller.process_events(event),
+			CameraMode::Fps => self.fps_controller.process_events(event),
+			CameraMode::Orbit => self.orbit_controller.process_events(event),
+		};
+		if consumed {
+			return true;
+		}
+
+		match event {
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::V),
+						..
+					},
+				..
+			} => {
+				// Cycle through vsync, triple buffering and no vsync,
+				// skipping whatever the surface doesn't support
+				let modes = [
+					wgpu::PresentMode::Fifo,
+					wgpu::PresentMode::Mailbox,
+					wgpu::PresentMode::Immediate,
+				];
+				let current = modes.iter().position(|mode| *mode == self.config.present_mode).unwrap_or(0);
+				let next = (1..=modes.len())
+					.map(|offset| modes[(current + offset) % modes.len()])
+					.find(|mode| self.supported_present_modes.contains(mode))
+					.unwrap_or(wgpu::PresentMode::Fifo);
+				self.set_present_mode(next);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::M),
+						..
+					},
+				..
+			} => {
+				// Toggle MSAA
+				self.set_sample_count(if self.framebuffer.sample_count() > 1 { 1 } else { 4 });
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::C),
+						..
+					},
+				..
+			} => {
+				self.set_camera_mode(match self.camera_mode {
+					CameraMode::Keyboard => CameraMode::Fps,
+					CameraMode::Fps => CameraMode::Orbit,
+					CameraMode::Orbit => CameraMode::Keyboard,
+				});
+				log::info!("Camera mode {:?}", self.camera_mode);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::G),
+						..
+					},
+				..
+			} => {
+				self.set_post_effect(self.post_process.effect().next());
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::B),
+						..
+					},
+				..
+			} => {
+				self.show_skybox = !self.show_skybox;
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::T),
+						..
+					},
+				..
+			} => {
+				// Motion trails
+				self.clear_enabled = !self.clear_enabled;
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::J),
+						..
+					},
+				..
+			} => {
+				// Switch between perspective and orthographic, keeping the clip planes
+				let (near, far) = self.camera.clip_planes();
+				self.set_projection(match self.camera.projection {
+					Projection::Perspective { .. } => Projection::Orthographic {
+						left: -5.0,
+						right: 5.0,
+						bottom: -5.0,
+						top: 5.0,
+						near,
+						far,
+					},
+					Projection::Orthographic { .. } => Projection::Perspective {
+						fovy: 45.0,
+						znear: near,
+						zfar: far,
+					},
+				});
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Y),
+						..
+					},
+				..
+			} => {
+				// Tile the texture 4x4 or not at all
+				let scale = if self.texture_transform.value().scale == [1.0, 1.0] { 4.0 } else { 1.0 };
+				self.set_uv_scale([scale, scale], [0.0, 0.0]);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::N),
+						..
+					},
+				..
+			} => {
+				self.set_clear_color_follows_cursor(!self.clear_color_follows_cursor);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::O),
+						..
+					},
+				..
+			} => {
+				// Point cloud
+				self.set_topology(if self.topology == wgpu::PrimitiveTopology::PointList {
+					wgpu::PrimitiveTopology::TriangleList
+				} else {
+					wgpu::PrimitiveTopology::PointList
+				});
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::X),
+						..
+					},
+				..
+			} => {
+				self.show_debug_lines = !self.show_debug_lines;
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::K),
+						..
+					},
+				..
+			} => {
+				// Vertex colors instead of the texture
+				self.use_vertex_colors = !self.use_vertex_colors;
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::U),
+						..
+					},
+				..
+			} => {
+				// Solid color instead of the texture
+				self.set_untextured(!self.untextured);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Z),
+						..
+					},
+				..
+			} => {
+				// Alpha cutout, try it on the cutout texture (key 3)
+				let cutoff = if self.shading.value().alpha_cutoff > 0.0 { 0.0 } else { 0.5 };
+				self.set_alpha_cutoff(cutoff);
+				true
+			}
+
+			// There's no file system to load from on the web
+			#[cfg(not(target_arch = "wasm32"))]
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::R),
+						..
+					},
+				..
+			} => {
+				if let Err(error) = self.load_obj(concat!(env!("CARGO_MANIFEST_DIR"), "/res/cube.obj")) {
+					log::error!("Couldn't load the model: {:#}", error);
+				}
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::I),
+						..
+					},
+				..
+			} => {
+				let pulse = if self.shading.value().pulse > 0.0 { 0.0 } else { 0.05 };
+				self.set_pulse(pulse);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Space),
+						..
+					},
+				..
+			} => {
+				self.spawn_particles(200);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::P),
+						..
+					},
+				..
+			} => {
+				// Toggle wireframe
+				self.set_polygon_mode(match self.polygon_mode {
+					wgpu::PolygonMode::Fill => wgpu::PolygonMode::Line,
+					_ => wgpu::PolygonMode::Fill,
+				});
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F),
+						..
+					},
+				..
+			} => {
+				self.show_overlay = !self.show_overlay;
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(keycode @ (
+							VirtualKeyCode::Key1 | VirtualKeyCode::Key2 | VirtualKeyCode::Key3
+							| VirtualKeyCode::Key4 | VirtualKeyCode::Key5 | VirtualKeyCode::Key6
+							| VirtualKeyCode::Key7 | VirtualKeyCode::Key8 | VirtualKeyCode::Key9
+						)),
+						..
+					},
+				..
+			} => {
+				// Key1 to Key9 are declared in order, so they map straight to an index
+				let index = *keycode as usize - VirtualKeyCode::Key1 as usize;
+				if index < self.textures.len() {
+					self.active_texture = index;
+				}
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(keycode @ (
+							VirtualKeyCode::LBracket | VirtualKeyCode::RBracket
+							| VirtualKeyCode::Comma | VirtualKeyCode::Period
+						)),
+						..
+					},
+				..
+			} => {
+				// [ and ] move the near plane, , and . the far plane. The
+				// new planes are picked up by the next update.
+				let (near, far) = self.camera.clip_planes();
+				let (near, far) = match keycode {
+					VirtualKeyCode::LBracket => (near * 0.5, far),
+					VirtualKeyCode::RBracket => (near * 2.0, far),
+					VirtualKeyCode::Comma => (near, far * 0.5),
+					_ => (near, far * 2.0),
+				};
+				self.camera.set_clip_planes(near, far);
+				let (near, far) = self.camera.clip_planes();
+				log::info!("Clip planes: {}..{}", near, far);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::L),
+						..
+					},
+				..
+			} => {
+				// Swing the light around the vertical axis
+				let rotation = cgmath::Quaternion::from_angle_y(cgmath::Deg(15.0));
+				let direction = rotation.rotate_vector(self.light_uniform.value().direction.into());
+				self.set_light_direction(direction);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::H),
+						..
+					},
+				..
+			} => {
+				let enabled = self.light_uniform.value().tone_mapping == 0;
+				self.set_tone_mapping(enabled);
+				true
+			}
+
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(keycode @ (VirtualKeyCode::PageUp | VirtualKeyCode::PageDown)),
+						..
+					},
+				..
+			} => {
+				let factor = if *keycode == VirtualKeyCode::PageUp { EXPOSURE_STEP } else { 1.0 / EXPOSURE_STEP };
+				self.set_exposure(self.exposure() * factor);
+				log::info!("Exposure {}", self.exposure());
+				true
+			}
+
+			WindowEvent::CursorMoved { position, .. } if self.clear_color_follows_cursor => {
+				// Map the cursor across the window to the red and green channels
+				self.set_clear_color(wgpu::Color {
+					r: position.x / self.size.width as f64,
+					g: position.y / self.size.height as f64,
+					b: 0.0,
+					a: 1.0,
+				});
+				true
+			}
+
+			_ => false,
+		}
+	}
+
+	pub fn device_input(&mut self, event: &DeviceEvent) {
+		if let DeviceEvent::MouseMotion { delta } = event {
+			if self.camera_mode == CameraMode::Fps {
+				self.fps_controller.process_mouse_motion(delta.0, delta.1);
+			}
+		}
+	}
+
+	// Rebuilds the render pipeline from shader.wgsl on disk. If the new
+	// shader doesn't compile the previous pipeline is kept.
+	#[cfg(feature = "hot-reload")]
+	pub fn reload_shader(&mut self) {
+		let shader = wgpu::ShaderModuleDescriptor {
+			label: Some("shader.wgsl"),
+			source: wgpu::ShaderSource::Wgsl(hot_reload::read_shader().into()),
+		};
+
+		match self.set_shader(shader) {
+			Ok(()) => log::info!("Reloaded {}", hot_reload::SHADER_PATH),
+			Err(e) => log::error!("Shader reload failed, keeping the previous pipeline: {}", e),
+		}
+	}
+
+	pub fn update(&mut self) {
+		let dt = self.frame_timer.tick();
+		self.step(dt);
+	}
+
+	// Like update, but advances by `dt` instead of the time since the last
+	// frame, so a fixed timestep gives the same frames however long each
+	// one takes to render, see run_recording. The frame timer isn't ticked,
+	// so the overlay's FPS counter doesn't follow `dt`.
+	pub fn step(&mut self, dt: std::time::Duration) {
+		#[cfg(feature = "hot-reload")]
+		if self.shader_watcher.has_changed() {
+			self.reload_shader();
+		}
+
+		match self.camera_mode {
+			CameraMode::Keyboard => self.camera_controller.update_camera(&mut self.camera),
+			CameraMode::Fps => self.fps_controller.update_camera(&mut self.camera, dt),
+			CameraMode::Orbit => self.orbit_controller.update_camera(&mut self.camera),
+		}
+		let mut camera_raw = *self.camera_uniform.get(CAMERA_SLOT);
+		camera_raw.update_view_proj(&self.camera);
+		// Written to the buffer through the staging belt while encoding
+		self.camera_uniform.set(CAMERA_SLOT, camera_raw);
+		self.skybox.update(&self.queue, &self.camera);
+		self.cull_meshes();
+		if let Some(particles) = &mut self.particles {
+			particles.update(&self.queue, dt);
+		}
+		self.time += dt;
+		self.time_uniform.update(&self.queue, frame_timer::TimeUniform::new(self.time.as_secs_f32()));
+
+		self.debug_lines.clear();
+		if self.show_debug_lines {
+			let origin = cgmath::Point3::new(0.0, 0.0, 0.0);
+			self.debug_lines.line(origin, cgmath::Point3::new(1.0, 0.0, 0.0), [1.0, 0.0, 0.0]);
+			self.debug_lines.line(origin, cgmath::Point3::new(0.0, 1.0, 0.0), [0.0, 1.0, 0.0]);
+			self.debug_lines.line(origin, cgmath::Point3::new(0.0, 0.0, 1.0), [0.0, 0.0, 1.0]);
+
+			// One box per instance around every mesh it draws, the same
+			// boxes cull_meshes tests against the frustum
+			let meshes = if self.use_vertex_colors {
+				self.color_meshes.borrow()
+			} else {
+				self.meshes.borrow()
+			};
+			for instance in self.instances.iter() {
+				let matrix = instance.to_matrix();
+				let aabb = meshes
+					.iter()
+					.map(|mesh| mesh.aabb.transformed(&matrix))
+					.reduce(|a, b| a.union(&b));
+				if let Some(aabb) = aabb {
+					self.debug_lines.aabb(aabb.min, aabb.max, [1.0, 0.0, 1.0]);
+				}
+			}
+		}
+	}
+
+	// Every mesh is drawn once per instance, so a mesh is only skipped when
+	// all of its instances are off screen
+	fn cull_meshes(&mut self) {
+		let planes = self.camera.frustum_planes();
+		let meshes = if self.use_vertex_colors {
+			self.color_meshes.borrow()
+		} else {
+			self.meshes.borrow()
+		};
+
+		self.visible_meshes = meshes
+			.iter()
+			.map(|mesh| {
+				self.instances
+					.iter()
+					.map(|instance| mesh.aabb.transformed(&instance.to_matrix()))
+					.any(|aabb| !aabb.is_outside(&planes))
+			})
+			.collect();
+
+		let drawn = self.visible_meshes.iter().filter(|&&visible| visible).count();
+		self.cull_stats = CullStats {
+			drawn,
+			culled: self.visible_meshes.len() - drawn,
+		};
+	}
+
+	pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+		let (output, view) = self.acquire_output()?;
+
+		self.render_to(&view)?;
+		if let Some(output) = output {
+			output.present();
+		}
+
+		Ok(())
+	}
+
+	// The next frame's texture and a view of it. The texture is None when
+	// rendering offscreen, where there's nothing to present.
+	fn acquire_output(&self) -> Result<(Option<wgpu::SurfaceTexture>, wgpu::TextureView), wgpu::SurfaceError> {
+		Ok(match &self.target {
+			RenderTarget::Surface(surface) => {
+				let output = surface.get_current_texture()?;
+				let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+				(Some(output), view)
+			}
+			RenderTarget::Offscreen(texture) => (None, texture.create_view(&wgpu::TextureViewDescriptor::default())),
+		})
+	}
+
+	// Presents a frame that's only `color`, skipping the scene and every
+	// stage. Handy for checking that the surface works at all, or as the
+	// starting point when another renderer draws the frame. Errors are the
+	// same as render's.
+	pub fn clear(&mut self, color: wgpu::Color) -> Result<(), wgpu::SurfaceError> {
+		let (output, view) = self.acquire_output()?;
+
+		let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+			label: Some("Clear Encoder"),
+		});
+		encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("Clear Only Pass"),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view: &view,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: wgpu::LoadOp::Clear(color),
+					store: true,
+				},
+			})],
+			depth_stencil_attachment: None,
+		});
+		self.queue.submit(std::iter::once(encoder.finish()));
+
+		if let Some(output) = output {
+			output.present();
+		}
+
+		Ok(())
+	}
+
+	// Renders a frame into `view` instead of the window, e.g. an XR eye
+	// texture or another renderer's target. The view has to have the
+	// surface_format and RENDER_ATTACHMENT usage, and should be the State's
+	// size (resize it to match), since the scene and overlay are laid out
+	// for that. Nothing here fails yet, the error is there so it can be
+	// handled like render's.
+	pub fn render_to(&mut self, view: &wgpu::TextureView) -> Result<(), wgpu::SurfaceError> {
+		let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+			label: Some("Render Encoder"),
+		});
 
-#[cfg(target_arch="wasm32")]
-use wasm_bindgen::prelude::*;
+		self.prepare_frame(&mut encoder);
+		if let Some(gpu_timer) = &mut self.gpu_timer {
+			gpu_timer.begin(&mut encoder);
+		}
+		self.record_stages(&mut encoder, view);
+		if let Some(gpu_timer) = &mut self.gpu_timer {
+			gpu_timer.end(&mut encoder);
+		}
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
-}
+		// The belt's writes have to be finished before submitting, and its
+		// chunks can only be reused once the GPU is done with them
+		self.staging_belt.finish();
+		// Submit will accept anything that implements IntoIter
+		self.queue.submit(std::iter::once(encoder.finish()));
+		self.staging_belt.recall();
+		if let Some(gpu_timer) = &mut self.gpu_timer {
+			gpu_timer.end_frame(&self.device);
+		}
 
-impl Vertex {
-	const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
-		0 => Float32x3,	// Position
-		1 => Float32x2, // Texture coordinate
-	];
+		Ok(())
+	}
 
-	fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-		use std::mem;
+	// Draws `text` in the next rendered frame only, so call it every frame
+	// for text that should stay. (x, y) is the top left corner in physical
+	// pixels from the top left of the window, and the font is 8 pixels per
+	// em times `scale`. Any UTF-8 works, characters the bundled DejaVu Sans
+	// Mono doesn't have show up as boxes.
+	pub fn draw_text(&mut self, text: &str, x: f32, y: f32, scale: f32, color: [f32; 4]) {
+		self.text_renderer.queue_text(text, x, y, scale, color);
+	}
 
-		wgpu::VertexBufferLayout  {
-			array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
-			step_mode: wgpu::VertexStepMode::Vertex,
-			attributes: &Self::ATTRIBS,
+	// Draws part of one of the scene's textures over the next rendered frame
+	// only, like draw_text. `dest` is in physical pixels from the top left of
+	// the window, `src` in texture coordinates from 0 to 1 (Rect::FULL for
+	// the whole texture), and the texture's colors are multiplied by `tint`.
+	// Sprites are drawn in the order they're queued, on top of the scene
+	// and under the overlay.
+	pub fn draw_sprite(&mut self, tex_index: usize, dest: Rect, src: Rect, tint: [f32; 4]) {
+		if tex_index >= self.bind_groups.len() {
+			log::warn!("Texture index {} out of range, there are {} textures", tex_index, self.bind_groups.len());
+			return;
 		}
+		self.sprite_batch.queue_sprite(tex_index, dest, src, tint);
 	}
-}
 
-const VERTICES: &[Vertex] = &[
-    // Changed
-    Vertex { position: [-0.0868241, 0.49240386, 0.0], tex_coords: [0.4131759, 0.00759614], }, // A
-    Vertex { position: [-0.49513406, 0.06958647, 0.0], tex_coords: [0.0048659444, 0.43041354], }, // B
-    Vertex { position: [-0.21918549, -0.44939706, 0.0], tex_coords: [0.28081453, 0.949397], }, // C
-    Vertex { position: [0.35966998, -0.3473291, 0.0], tex_coords: [0.85967, 0.84732914], }, // D
-    Vertex { position: [0.44147372, 0.2347359, 0.0], tex_coords: [0.9414737, 0.2652641], }, // E
-];
+	// Uploads everything the stages read this frame. Shared by the
+	// swapchain and capture paths.
+	fn prepare_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+		self.camera_uniform.write_with_belt(&mut self.staging_belt, encoder, &self.device);
+		self.debug_lines.upload(&self.device, &self.queue);
+		// Moved before the stages draw them
+		if let Some(particles) = &self.particles {
+			particles.simulate(encoder);
+		}
 
+		if self.show_overlay {
+			let mut overlay = format!(
+				"FPS: {:.0}\n{:.2} ms",
+				self.frame_timer.fps(),
+				self.frame_timer.frame_time_ms(),
+			);
+			if let Some(gpu_time) = self.last_gpu_time() {
+				overlay += &format!("\nGPU: {} us", gpu_time.as_micros());
+			}
+			overlay += &format!("\nMeshes: {} drawn, {} culled", self.cull_stats.drawn, self.cull_stats.culled);
+			// Keep the text the same size on HiDPI screens
+			let scale = (2.0 * self.scale_factor) as f32;
+			let margin = 4.0 * scale;
+			self.text_renderer.queue_text(&overlay, margin, margin, scale, [0.0, 0.0, 0.0, 1.0]);
+		}
+		self.text_renderer.prepare(&self.device, &self.queue, self.size);
+		self.sprite_batch.prepare(&self.device, &self.queue, self.size);
+	}
 
-const INDICES: &[u16] = &[
-    0, 1, 4,
-    1, 2, 4,
-    2, 3, 4,
-];
+	fn record_stages(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+		for stage in &self.stages {
+			stage.record(encoder, view, self);
+		}
+	}
 
-struct State {
-	surface: wgpu::Surface,
-	device: wgpu::Device,
-	queue: wgpu::Queue,
-	config: wgpu::SurfaceConfiguration,
-	size: winit::dpi::PhysicalSize<u32>,
-	clear_color: wgpu::Color,
-	render_pipeline: wgpu::RenderPipeline,
-	vertex_buffer: wgpu::Buffer,
-	index_buffer: wgpu::Buffer,
-	num_indices: u32,
-	diffuse_bind_group: wgpu::BindGroup,
-	diffuse_texture: texture::Texture,
-	camera: camera::Camera,
-	camera_controller: camera::CameraController,
-	camera_uniform: camera::CameraUniform,
-	camera_buffer: wgpu::Buffer,
-	camera_bind_group: wgpu::BindGroup,
-}
+	// The stages render runs in order, see render_stage::default_stages.
+	// Insert a custom RenderPassStage here to add a pass to every frame.
+	pub fn stages_mut(&mut self) -> &mut Vec<Box<dyn render_stage::RenderPassStage>> {
+		&mut self.stages
+	}
 
-impl State {
-	// Creating some of the wgpu types requires async code
-	async fn new(window: &Window) -> Self {
-		let size = window.inner_size();
+	// Selects the scene camera's slot in camera_uniform's bind group
+	fn camera_offset(&self) -> wgpu::DynamicOffset {
+		self.camera_uniform.offset(CAMERA_SLOT)
+	}
 
-		// The instance is a handle to our GPU
-		// Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
-		let instance = wgpu::Instance::new(wgpu::Backends::all());
-		let surface = unsafe { instance.create_surface(window) };
-		let adapter = instance.request_adapter(
-			&wgpu::RequestAdapterOptions {
-				power_preference: wgpu::PowerPreference::default(),
-				compatible_surface: Some(&surface),
-				force_fallback_adapter: false,
-			},
-		).await.unwrap();
-
-		// The code below might be better than the one above
-		/*
-		let adapter = instance
-    		.enumerate_adapters(wgpu::Backends::all())
-    		.filter(|adapter| {
-        		// Check if this adapter supports our surface
-        		!surface.get_supported_formats(&adapter).is_empty()
-    		})
-    		.next()
-    		.unwrap();*/
-		
-		let (device, queue) = adapter.request_device(
-			&wgpu::DeviceDescriptor {
-				features: wgpu::Features::empty(),
-
-				// WebGL doesn't support all of wgpu's features, so if
-				// we're building for the web we'll have to disable some.
-				limits: if cfg!(target_arch = "wasm32") {
-					wgpu::Limits::downlevel_webgl2_defaults()
-				} else {
-					wgpu::Limits::default()
-				},
-				label: None
-			},
-			None,	// Trace path
-		).await.unwrap();
+	// The color attachment for passes drawing the scene. When multisampling
+	// that's the MSAA texture, resolved into scene_texture.
+	pub fn scene_attachment(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+		self.framebuffer.color_attachment(&self.scene_texture.view)
+	}
 
-		let config = wgpu::SurfaceConfiguration {
-			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-			format: surface.get_supported_formats(&adapter)[0],
-			width: size.width,
-			height: size.height,
-			present_mode: wgpu::PresentMode::Fifo,
-		};
-		surface.configure(&device, &config);
-
-		let diffuse_bytes = include_bytes!("happy-tree.png");
-		let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "diffuse_texture").unwrap();
-
-		let texture_bind_group_layout=
-			device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-				entries: &[
-					wgpu::BindGroupLayoutEntry {
-						binding: 0,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Texture {
-							multisampled: false,
-							view_dimension: wgpu::TextureViewDimension::D2,
-							sample_type: wgpu::TextureSampleType::Float { filterable: true },
-						},
-						count: None,
-					},
-					wgpu::BindGroupLayoutEntry {
-						binding: 1,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						// This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-					},
-				],
-				label: Some("texture_bind_group_layout"),
-			}
-		);
-	
-		let diffuse_bind_group = device.create_bind_group(
-			&wgpu::BindGroupDescriptor {
-				layout: &texture_bind_group_layout,
-				entries: &[
-					wgpu::BindGroupEntry {
-						binding: 0,
-						resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-					},
-					wgpu::BindGroupEntry {
-						binding: 1,
-						resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-					}
-				],
-				label: Some("diffuse_bind_group"),
-			}
+	// The depth buffer as the last rendered frame left it, one value per
+	// pixel row by row from the top left. Raw values are what the depth test
+	// compared, 0 to 1 with reverse-Z flipping them, see Camera::reverse_z.
+	// With `linearize` they're turned into distances from the camera using
+	// its near and far planes, see Camera::linearize_depth. Multisampled
+	// depth gives the first sample of each pixel. Waits for the GPU, and is
+	// empty if the device was lost.
+	pub fn capture_depth(&self, linearize: bool) -> Vec<f32> {
+		let depths = self.depth_capture.capture(
+			&self.device,
+			&self.queue,
+			self.framebuffer.depth_texture(),
+			self.size,
+			self.framebuffer.sample_count(),
 		);
-		
-		let camera = camera::Camera {
-			// position the camera one unit up and 2 units back
-			// +z is out of the screen
-			eye: (0.0, 1.0, 2.0).into(),
-			// have it look at the origin
-			target: (0.0, 0.0, 0.0).into(),
-			// which way is "up"
-			up: cgmath::Vector3::unit_y(),
-			aspect: config.width as f32 / config.height as f32,
-			fovy: 45.0,
-			znear: 0.1,
-			zfar: 100.0,
+		let Some(mut depths) = depths else {
+			log::warn!("Couldn't read back the depth buffer");
+			return Vec::new();
 		};
 
-		let camera_controller = camera::CameraController::new(0.2);
-	
-		let mut camera_uniform = camera::CameraUniform::new();
-		camera_uniform.update_view_proj(&camera);
-	
-		let camera_buffer = device.create_buffer_init(
-			&wgpu::util::BufferInitDescriptor {
-				label: Some("Camera Buffer"),
-				contents: bytemuck::cast_slice(&[camera_uniform]),
-				usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		if linearize {
+			for depth in &mut depths {
+				*depth = self.camera.linearize_depth(*depth);
 			}
-		);
-	
-		let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-			entries: &[
-				wgpu::BindGroupLayoutEntry {
-					binding: 0,
-					visibility: wgpu::ShaderStages::VERTEX,
-					ty: wgpu::BindingType::Buffer {
-						ty: wgpu::BufferBindingType::Uniform,
-						has_dynamic_offset: false,
-						min_binding_size: None,
-					},
-					count: None,
-				}
-			],
-			label: Some("camera_bind_group_layout"),
-		});
-	
-		let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-			layout: &camera_bind_group_layout,
-			entries: &[
-				wgpu::BindGroupEntry {
-					binding: 0,
-					resource: camera_buffer.as_entire_binding(),
-				}
-			],
-			label: Some("camera_bind_group"),
+		}
+		depths
+	}
+
+	// Renders a frame through the same stages as render, overlay included,
+	// and reads it back. Works the same with and without multisampling:
+	// the scene passes resolve the MSAA texture into scene_texture, and
+	// what gets copied is the single-sampled target post_process draws
+	// that into, never the multisampled texture itself (which can't be
+	// copied from). Headless states resolve the same way, so no separate
+	// resolve step is needed. Waits for the GPU, and renders a frame of its
+	// own, so it's meant for debugging and tests rather than every frame.
+	pub fn capture_frame(&mut self) -> Result<image::RgbaImage, wgpu::SurfaceError> {
+		let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+			label: Some("Capture Encoder"),
 		});
+		self.prepare_frame(&mut encoder);
 
-		let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
+		let size = wgpu::Extent3d {
+			width: self.config.width,
+			height: self.config.height,
+			depth_or_array_layers: 1,
+		};
 
-		let render_pipeline_layout =
-			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-				label: Some("Render Pipeline Layout"),
-				bind_group_layouts: &[
-					&texture_bind_group_layout,
-					&camera_bind_group_layout
-				],
-				push_constant_ranges: &[],
+		// Headless states already render into a texture we can copy from.
+		// Otherwise use one with the surface's format so the render pipeline
+		// can target it.
+		let capture_texture;
+		let texture = match &self.target {
+			RenderTarget::Offscreen(texture) => texture,
+			RenderTarget::Surface(_) => {
+				capture_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+					label: Some("Capture Texture"),
+					size,
+					mip_level_count: 1,
+					sample_count: 1,
+					dimension: wgpu::TextureDimension::D2,
+					format: self.config.format,
+					usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+				});
+				&capture_texture
 			}
-		);
+		};
+		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-			label: Some("Render Pipeline"),
-			layout: Some(&render_pipeline_layout),
-			vertex: wgpu::VertexState {
-				module: &shader,
-				entry_point: "vs_main", // Vertex shader entry point function
-				buffers: &[ // Vertex buffers
-					Vertex::desc(),
-				],
-			},
-			fragment: Some(wgpu::FragmentState {
-				module: &shader,
-				entry_point: "fs_main", // Fragment shader entry point function
-				targets: &[Some(wgpu::ColorTargetState { // Output information
-					format: config.format,
-					blend: Some(wgpu::BlendState::REPLACE),
-					write_mask: wgpu::ColorWrites::ALL,
-				})],
-			}),
-			primitive: wgpu::PrimitiveState {
-				topology: wgpu::PrimitiveTopology::TriangleList, // Every three vertices correspond to one triangle
-				strip_index_format: None,
-				front_face: wgpu::FrontFace::Ccw,
-				cull_mode: Some(wgpu::Face::Back),
-				// Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-				polygon_mode: wgpu::PolygonMode::Fill,
-				// Requires Features::DEPTH_CLIP_CONTROL
-				unclipped_depth: false,
-				// Requires Features::CONSERVATIVE_RASTERIZATION
-				conservative: false,
-			},
+		// Rows copied into a buffer have to be padded to a multiple of 256 bytes
+		let unpadded_bytes_per_row = 4 * size.width;
+		let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+			* wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
 
-			depth_stencil: None,
-			multisample: wgpu::MultisampleState {
-				count: 1,
-				mask: !0, // Use all samples
-				alpha_to_coverage_enabled: false,
-			},
-			multiview: None,
+		let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Capture Buffer"),
+			size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
 		});
 
-		let vertex_buffer = device.create_buffer_init(
-			&wgpu::util::BufferInitDescriptor {
-				label: Some("Vertex Buffer"),
-				contents: bytemuck::cast_slice(VERTICES),
-				usage: wgpu::BufferUsages::VERTEX,
-			}
-		);
+		self.record_stages(&mut encoder, &view);
 
-		let index_buffer = device.create_buffer_init(
-			&wgpu::util::BufferInitDescriptor {
-				label: Some("Index Buffer"),
-				contents: bytemuck::cast_slice(INDICES),
-				usage: wgpu::BufferUsages::INDEX,
-			}
+		encoder.copy_texture_to_buffer(
+			wgpu::ImageCopyTexture {
+				texture,
+				mip_level: 0,
+				origin: wgpu::Origin3d::ZERO,
+				aspect: wgpu::TextureAspect::All,
+			},
+			wgpu::ImageCopyBuffer {
+				buffer: &buffer,
+				layout: wgpu::ImageDataLayout {
+					offset: 0,
+					bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+					rows_per_image: std::num::NonZeroU32::new(size.height),
+				},
+			},
+			size,
 		);
 
-		let num_indices = INDICES.len() as u32;
+		self.staging_belt.finish();
+		self.queue.submit(std::iter::once(encoder.finish()));
+		self.staging_belt.recall();
 
-		Self {
-			surface,
-			device,
-			queue,
-			config,
-			size,
-			clear_color: wgpu::Color::WHITE,
-			render_pipeline,
-			vertex_buffer,
-			index_buffer,
-			num_indices,
-			diffuse_bind_group,
-			diffuse_texture,
-			camera,
-			camera_controller,
-			camera_uniform,
-			camera_buffer,
-			camera_bind_group,
+		let slice = buffer.slice(..);
+		let (sender, receiver) = std::sync::mpsc::channel();
+		slice.map_async(wgpu::MapMode::Read, move |result| {
+			let _ = sender.send(result);
+		});
+		self.device.poll(wgpu::Maintain::Wait);
+
+		// Mapping only fails if the device went away underneath us
+		match receiver.recv() {
+			Ok(Ok(())) => {}
+			_ => return Err(wgpu::SurfaceError::Lost),
 		}
 
-	}
+		let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * size.height) as usize);
+		{
+			let data = slice.get_mapped_range();
+			for row in data.chunks(padded_bytes_per_row as usize) {
+				pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+			}
+		}
+		buffer.unmap();
 
-	fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-		if new_size.width > 0 && new_size.height > 0 {
-			self.size = new_size;
-			self.config.width = new_size.width;
-			self.config.height = new_size.height;
-			self.surface.configure(&self.device, &self.config);
+		if matches!(self.config.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+			for pixel in pixels.chunks_exact_mut(4) {
+				pixel.swap(0, 2);
+			}
 		}
+
+		Ok(image::RgbaImage::from_raw(size.width, size.height, pixels)
+			.expect("Capture buffer should match the frame size"))
 	}
+}
 
-	fn input(&mut self, event: &WindowEvent) -> bool {
-		/*match event {
-			WindowEvent::CursorMoved { position, .. } => {
-				self.clear_color = wgpu::Color {
-					r: position.to_logical(self.config.width as f64).x,
-					g: position.to_logical(self.config.height as f64).y,
-					b: 0.0,
-					a: 1.0,
-				};
 
-				true
-			}
 
-			_ => false
-		}*/
+// How run_with_options sets up the main window
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+	pub title: String,
+	// In logical pixels, so the window is the same size on high DPI
+	// displays. Ignored on the web, where the canvas decides.
+	pub width: u32,
+	pub height: u32,
+	pub resizable: bool,
+	pub decorations: bool,
+	// Opens a second window looking down on the same scene. Native only,
+	// the web build has the one canvas.
+	pub extra_top_down_view: bool,
+}
 
-		self.camera_controller.process_events(event)
+impl Default for WindowOptions {
+	fn default() -> Self {
+		Self {
+			title: "Learning wgpu".to_string(),
+			width: 800,
+			height: 600,
+			resizable: true,
+			decorations: true,
+			extra_top_down_view: false,
+		}
 	}
+}
+
+// What a State was changed to since it was created, carried over by
+// recreate_states. Custom shaders, meshes, materials and render targets
+// belong to the lost device and are not.
+#[cfg(not(target_arch = "wasm32"))]
+struct RuntimeSettings {
+	state_config: StateConfig,
+	camera: camera::Camera,
+	camera_mode: CameraMode,
+	present_mode: wgpu::PresentMode,
+	sample_count: u32,
+	polygon_mode: wgpu::PolygonMode,
+	topology: wgpu::PrimitiveTopology,
+	stencil: wgpu::StencilState,
+	stencil_reference: u32,
+	untextured: bool,
+	use_vertex_colors: bool,
+	active_texture: usize,
+	clear_color: wgpu::Color,
+	clear_color_follows_cursor: bool,
+	clear_enabled: bool,
+	show_skybox: bool,
+	show_debug_lines: bool,
+	show_overlay: bool,
+	post_effect: post_process::PostEffect,
+	light: light::LightUniform,
+	shading: light::ShadingUniform,
+	texture_transform: texture::TextureTransform,
+}
 
-	fn update(&mut self) {
-		self.camera_controller.update_camera(&mut self.camera); 
-		self.camera_uniform.update_view_proj(&self.camera);
-		self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+#[cfg(not(target_arch = "wasm32"))]
+impl RuntimeSettings {
+	fn of(state: &State) -> Self {
+		Self {
+			state_config: state.state_config.clone(),
+			camera: state.camera.clone(),
+			camera_mode: state.camera_mode,
+			present_mode: state.config.present_mode,
+			sample_count: state.framebuffer.sample_count(),
+			polygon_mode: state.polygon_mode,
+			topology: state.topology,
+			stencil: state.stencil.clone(),
+			stencil_reference: state.stencil_reference,
+			untextured: state.untextured,
+			use_vertex_colors: state.use_vertex_colors,
+			active_texture: state.active_texture,
+			clear_color: state.clear_color,
+			clear_color_follows_cursor: state.clear_color_follows_cursor,
+			clear_enabled: state.clear_enabled,
+			show_skybox: state.show_skybox,
+			show_debug_lines: state.show_debug_lines,
+			show_overlay: state.show_overlay,
+			post_effect: state.post_process.effect(),
+			light: *state.light_uniform.value(),
+			shading: *state.shading.value(),
+			texture_transform: *state.texture_transform.value(),
+		}
 	}
 
-	fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-		let output = self.surface.get_current_texture()?;
+	// Through the setters, so the pipelines are rebuilt to match
+	fn apply(self, state: &mut State) {
+		let reverse_z = self.camera.reverse_z;
+		state.camera = self.camera;
+		state.set_reverse_z(reverse_z);
+		state.set_camera_mode(self.camera_mode);
+		state.set_present_mode(self.present_mode);
+		state.set_sample_count(self.sample_count);
+		state.set_polygon_mode(self.polygon_mode);
+		state.set_topology(self.topology);
+		state.set_stencil(self.stencil);
+		state.set_stencil_reference(self.stencil_reference);
+		state.set_untextured(self.untextured);
+		state.use_vertex_colors = self.use_vertex_colors;
+		// The textures are recreated in the same order
+		state.active_texture = self.active_texture;
+		state.set_clear_color(self.clear_color);
+		state.set_clear_color_follows_cursor(self.clear_color_follows_cursor);
+		state.clear_enabled = self.clear_enabled;
+		state.show_skybox = self.show_skybox;
+		state.show_debug_lines = self.show_debug_lines;
+		state.show_overlay = self.show_overlay;
+		state.set_post_effect(self.post_effect);
+		state.light_uniform.update(&state.queue, self.light);
+		state.shading.update(&state.queue, self.shading);
+		state.texture_transform.update(&state.queue, self.texture_transform);
+	}
+}
 
-		let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+// Rebuilds every window's State on a new device after the old one was
+// lost, from the StateConfig the first one was created with, and carries
+// their RuntimeSettings over. Like in run(), the first window gets a fresh
+// device and the rest share it. Windows whose State can't be recreated are
+// closed. Blocks on the adapter and device requests, so native only; on
+// the web (which doesn't report device loss yet anyway) nothing happens.
+#[cfg(not(target_arch = "wasm32"))]
+fn recreate_states(windows: Vec<(Window, State)>) -> Vec<(Window, State)> {
+	log::warn!("Recreating the renderer after the GPU device was lost");
 
-		let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-			label: Some("Render Encoder"),
-		});
+	// Every surface has to go before the windows get new ones
+	let (windows, settings): (Vec<_>, Vec<_>) = windows
+		.into_iter()
+		.map(|(window, state)| (window, RuntimeSettings::of(&state)))
+		.unzip();
 
-		{
-			let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-				label: Some("Render Pass"),
-				color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-					view: &view,
-					resolve_target: None,
-					ops: wgpu::Operations {
-						load: wgpu::LoadOp::Clear(self.clear_color),
-						store: true,
-					},
-				})],
-				depth_stencil_attachment: None,
-			});
+	let mut recreated: Vec<(Window, State)> = Vec::with_capacity(windows.len());
+	for (window, settings) in windows.into_iter().zip(settings) {
+		let state = match recreated.first() {
+			Some((_, first)) => State::new_sharing(&window, first),
+			None => pollster::block_on(State::new_with_config(&window, settings.state_config.clone())),
+		};
+		match state {
+			Ok(mut state) => {
+				settings.apply(&mut state);
+				recreated.push((window, state));
+			}
+			Err(e) => log::error!("Couldn't recreate the renderer: {}", e),
+		}
+	}
+	recreated
+}
 
-			render_pass.set_pipeline(&self.render_pipeline);
+// Saves a frame from capture_frame as screenshot_<milliseconds since the
+// Unix epoch>.png in the working directory. The PNG is encoded on its own
+// thread, so the window doesn't hitch while it's written.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_screenshot(image: image::RgbaImage) {
+	let millis = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map_or(0, |since_epoch| since_epoch.as_millis());
+	let path = std::path::PathBuf::from(format!("screenshot_{}.png", millis));
+	std::thread::spawn(move || match image.save(&path) {
+		Ok(()) => log::info!("Saved a screenshot to {}", path.display()),
+		Err(e) => log::warn!("Couldn't write {}: {}", path.display(), e),
+	});
+}
 
-			render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-			render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-			render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-			render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-			
-			render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-		}
+// There's no file system on the web, so the screenshot is downloaded
+// through a link to a data URL instead. Without threads, the PNG is
+// encoded right away.
+#[cfg(target_arch = "wasm32")]
+fn save_screenshot(image: image::RgbaImage) {
+	use wasm_bindgen::JsCast;
 
-		// Submit will accept anything that implements IntoIter
-		self.queue.submit(std::iter::once(encoder.finish()));
-		output.present();
+	let name = format!("screenshot_{}.png", js_sys::Date::now() as u64);
+	let mut png = Vec::new();
+	if let Err(e) = image::DynamicImage::ImageRgba8(image).write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png) {
+		log::warn!("Couldn't encode the screenshot: {}", e);
+		return;
+	}
 
-		Ok(())
+	// btoa takes a string with one char per byte
+	let bytes: String = png.iter().map(|&byte| byte as char).collect();
+	let downloaded = web_sys::window().and_then(|window| {
+		let base64 = window.btoa(&bytes).ok()?;
+		let link: web_sys::HtmlAnchorElement = window.document()?.create_element("a").ok()?.dyn_into().ok()?;
+		link.set_href(&format!("data:image/png;base64,{}", base64));
+		link.set_download(&name);
+		link.click();
+		Some(())
+	});
+	match downloaded {
+		Some(()) => log::info!("Downloading the screenshot as {}", name),
+		None => log::warn!("Couldn't start the screenshot download"),
 	}
 }
 
+// Renders `frames` frames offscreen at the default window size, stepping
+// by 1 / `fps` seconds each, and saves them to `out_dir` as
+// frame_00000.png, frame_00001.png and so on, e.g. to turn into a video
+// with ffmpeg. `out_dir` is created if needed. The overlay is hidden, its
+// FPS counter would show how fast the frames rendered instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn run_recording(frames: u32, fps: u32, out_dir: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+	use anyhow::Context;
+
+	if fps == 0 {
+		anyhow::bail!("Can't record at 0 FPS");
+	}
+	let out_dir = out_dir.as_ref();
+	std::fs::create_dir_all(out_dir).with_context(|| format!("Couldn't create {}", out_dir.display()))?;
+
+	let options = WindowOptions::default();
+	let mut state = State::new_headless(options.width, options.height, wgpu::TextureFormat::Rgba8UnormSrgb).await?;
+	state.show_overlay = false;
 
+	let dt = std::time::Duration::from_secs(1) / fps;
+	for frame in 0..frames {
+		state.step(dt);
+		let image = state.capture_frame()?;
+		let path = out_dir.join(format!("frame_{:05}.png", frame));
+		image.save(&path).with_context(|| format!("Couldn't write {}", path.display()))?;
+	}
+	Ok(())
+}
 
 #[cfg_attr(target_arch="wasm32", wasm_bindgen(start))]
 pub async fn run() {
+	run_with_options(WindowOptions::default()).await
+}
+
+pub async fn run_with_options(options: WindowOptions) {
 	cfg_if::cfg_if! {
 		if #[cfg(target_arch = "wasm32")] {
 			std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -408,7 +2945,13 @@ pub async fn run() {
 	
 
 	let event_loop = EventLoop::new();
-	let window = WindowBuilder::new().build(&event_loop).unwrap();
+	let window = WindowBuilder::new()
+		.with_title(&options.title)
+		.with_inner_size(winit::dpi::LogicalSize::new(options.width, options.height))
+		.with_resizable(options.resizable)
+		.with_decorations(options.decorations)
+		.build(&event_loop)
+		.unwrap();
 
 	#[cfg(target_arch = "wasm32")] {
     	// Winit prevents sizing with CSS, so we have to set
@@ -428,16 +2971,52 @@ pub async fn run() {
     	    .expect("Couldn't append canvas to document body.");
 	}
 
-	let mut state = State::new(&window).await;
+	let state = match State::new_with_progress(&window, |phase| log::info!("Initializing: {:?}", phase)).await {
+		Ok(state) => state,
+		Err(e) => {
+			log::error!("Couldn't initialize renderer: {}", e);
+			return;
+		}
+	};
+	let mut windows = vec![(window, state)];
+
+	#[cfg(not(target_arch = "wasm32"))]
+	if options.extra_top_down_view {
+		let window = WindowBuilder::new().with_title("Top down").build(&event_loop).unwrap();
+		match State::new_sharing(&window, &windows[0].1) {
+			Ok(mut state) => {
+				state.camera.eye = (0.0, 10.0, 4.0).into();
+				windows.push((window, state));
+			}
+			Err(e) => log::error!("Couldn't create the second window: {}", e),
+		}
+	}
 
 	event_loop.run(move |event, _, control_flow| match event {
 		Event::WindowEvent { 
 			ref event,
 			window_id,
-		} if window_id == window.id() => if !state.input(event) {
+		} => {
+			let Some(index) = windows.iter().position(|(window, _)| window.id() == window_id) else {
+				return;
+			};
+			let (window, state) = &mut windows[index];
+			if state.handle_window_event(event) {
+				return;
+			}
+
 			match event {
-				WindowEvent::CloseRequested
-				| WindowEvent::KeyboardInput {
+				WindowEvent::CloseRequested => {
+					// Drop the surface before the window it was created from
+					let (window, state) = windows.remove(index);
+					drop(state);
+					drop(window);
+					if windows.is_empty() {
+						*control_flow = ControlFlow::Exit;
+					}
+				}
+
+				WindowEvent::KeyboardInput {
 					input:
 						KeyboardInput {
 							state: ElementState::Pressed,
@@ -447,38 +3026,224 @@ pub async fn run() {
 					..
 				} => *control_flow = ControlFlow::Exit,
 
-				WindowEvent::Resized(physical_size) => {
-					state.resize(*physical_size);
-				}
+				// On release, Windows doesn't report PrintScreen being pressed.
+				// Not also on P, which toggles the wireframe.
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Released,
+							virtual_keycode: Some(VirtualKeyCode::Snapshot),
+							..
+						},
+					..
+				} => match state.capture_frame() {
+					Ok(image) => save_screenshot(image),
+					Err(e) => log::warn!("Couldn't capture a screenshot: {:?}", e),
+				},
 
-				WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-					// new_inner_size is &&mut so we have to dereference it twice
-					state.resize(**new_inner_size);
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::F11),
+							..
+						},
+					..
+				} => {
+					// Ask the window rather than tracking a flag, the user can
+					// also leave fullscreen through the OS or browser. The
+					// surface is reconfigured by the Resized event that follows,
+					// on the web too: winit resizes the canvas to the screen
+					// when the browser enters fullscreen.
+					if window.fullscreen().is_some() {
+						window.set_fullscreen(None);
+					} else {
+						window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+					}
 				}
 
 				_ => {}
 			}
 		},
 
-		Event::RedrawRequested(window_id) if window_id == window.id() => {
+		// Device events aren't tied to a window. The FPS controller only
+		// reacts to them while its window has the right mouse button held.
+		Event::DeviceEvent { ref event, .. } => {
+			for (_, state) in windows.iter_mut() {
+				state.device_input(event);
+			}
+		}
+
+		Event::RedrawRequested(window_id) => {
+			let Some((_, state)) = windows.iter_mut().find(|(window, _)| window.id() == window_id) else {
+				return;
+			};
+			// Acquiring a frame from a 0x0 surface fails, or worse on some
+			// platforms, so minimized windows aren't drawn until restored
+			if state.is_minimized() {
+				return;
+			}
 			state.update();
 			match state.render() {
 				Ok(_) => {}
-				// Reconfigure the surface if lost
-				Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+				// A lost surface and an outdated one (e.g. the window was resized
+				// between acquiring the frame and presenting it) both mean the swap
+				// chain no longer matches the window, so reconfigure it right away
+				// instead of waiting for a Resized event that may never come.
+				Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => state.resize(state.size()),
 				// The system is out of memory, we should probably quit
 				Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-				// All other errors (Outdated, Timeout) should be resolved by the next frame
+				// All other errors (Timeout) should be resolved by the next frame
 				Err(e) => eprintln!("{:?}", e),
 			}
 		}
 
 		Event::MainEventsCleared => {
+			#[cfg(not(target_arch = "wasm32"))]
+			if windows.iter().any(|(_, state)| state.device_lost()) {
+				windows = recreate_states(std::mem::take(&mut windows));
+				if windows.is_empty() {
+					*control_flow = ControlFlow::Exit;
+					return;
+				}
+			}
+
 			// RedrawRequested will only trigger once, unless we manually
 			// request it.
-			window.request_redraw();
+			for (window, state) in windows.iter() {
+				if !state.is_minimized() {
+					window.request_redraw();
+				}
+			}
+			// With nothing to draw, sleep until an event comes in instead of
+			// spinning, e.g. the Resized that restores a window
+			*control_flow = if windows.iter().all(|(_, state)| state.is_minimized()) {
+				ControlFlow::Wait
+			} else {
+				ControlFlow::Poll
+			};
 		}
 
 		_ => {}
 	});
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// None when there's no adapter to render on, e.g. CI without a GPU, in
+	// which case the tests pass without checking anything
+	fn headless_state() -> Option<State> {
+		match pollster::block_on(State::new_headless(256, 192, wgpu::TextureFormat::Rgba8UnormSrgb)) {
+			Ok(mut state) => {
+				state.show_overlay = false;
+				Some(state)
+			}
+			Err(InitError::NoAdapter) => {
+				eprintln!("No adapter, skipping");
+				None
+			}
+			Err(e) => panic!("{}", e),
+		}
+	}
+
+	fn capture(state: &mut State, sample_count: u32) -> image::RgbaImage {
+		state.set_sample_count(sample_count);
+		state.step(std::time::Duration::ZERO);
+		state.capture_frame().unwrap()
+	}
+
+	#[test]
+	fn multisampling_only_changes_edges() {
+		let mut state = match headless_state() {
+			Some(state) => state,
+			None => return,
+		};
+		let single = capture(&mut state, 1);
+		let multi = capture(&mut state, 4);
+		if state.framebuffer.sample_count() != 4 {
+			eprintln!("4x multisampling isn't supported, skipping");
+			return;
+		}
+
+		// The scene shows up at all rather than just the clear color
+		let clear = single.get_pixel(0, 0);
+		assert!(multi.pixels().any(|pixel| pixel != clear));
+
+		// Resolving blends the pixels along the edges of triangles, and
+		// nothing else. Some drivers don't antialias at all, so no
+		// difference is fine.
+		let changed = single.pixels().zip(multi.pixels()).filter(|(a, b)| a != b).count();
+		let total = (single.width() * single.height()) as usize;
+		assert!(changed * 10 < total, "{} of {} pixels changed", changed, total);
+	}
+
+	#[test]
+	fn runtime_settings_carry_over() {
+		let mut changed = match headless_state() {
+			Some(state) => state,
+			None => return,
+		};
+		changed.set_post_effect(post_process::PostEffect::Invert);
+		changed.set_uv_scale([4.0, 4.0], [0.0, 0.0]);
+		changed.set_reverse_z(true);
+		changed.show_skybox = false;
+		changed.set_clear_color(wgpu::Color::RED);
+		let expected = capture(&mut changed, 1);
+		let settings = RuntimeSettings::of(&changed);
+		// Like after losing the device, the old State is gone first
+		drop(changed);
+
+		let mut fresh = headless_state().unwrap();
+		assert_ne!(expected, capture(&mut fresh, 1));
+		settings.apply(&mut fresh);
+		assert_eq!(expected, capture(&mut fresh, 1));
+	}
+
+	#[test]
+	fn broken_shader_keeps_the_pipelines() {
+		let mut state = match headless_state() {
+			Some(state) => state,
+			None => return,
+		};
+		let expected = capture(&mut state, 1);
+		let broken = wgpu::ShaderModuleDescriptor {
+			label: Some("Broken"),
+			source: wgpu::ShaderSource::Wgsl("@vertex fn vs_main() {}".into()),
+		};
+		assert!(state.set_shader(broken).is_err());
+		assert_eq!(expected, capture(&mut state, 1));
+	}
+
+	fn key_press(keycode: VirtualKeyCode) -> WindowEvent<'static> {
+		#[allow(deprecated)]
+		WindowEvent::KeyboardInput {
+			// Fine for events that never go through winit
+			device_id: unsafe { DeviceId::dummy() },
+			input: KeyboardInput {
+				scancode: 0,
+				state: ElementState::Pressed,
+				virtual_keycode: Some(keycode),
+				modifiers: ModifiersState::empty(),
+			},
+			is_synthetic: false,
+		}
+	}
+
+	#[test]
+	fn bound_keys_take_precedence_over_shortcuts() {
+		let mut state = match headless_state() {
+			Some(state) => state,
+			None => return,
+		};
+		// Y toggles the texture tiling unless it moves the camera
+		state.set_key_bindings(KeyBindings { forward: vec![VirtualKeyCode::Y], ..KeyBindings::default() });
+		assert!(state.input(&key_press(VirtualKeyCode::Y)));
+		assert_eq!(state.texture_transform.value().scale, [1.0, 1.0]);
+
+		state.set_key_bindings(KeyBindings::default());
+		assert!(state.input(&key_press(VirtualKeyCode::Y)));
+		assert_eq!(state.texture_transform.value().scale, [4.0, 4.0]);
+	}
+}
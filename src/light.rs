@@ -0,0 +1,54 @@
+// A single directional light, like the sun, plus the exposure the lit
+// color is scaled by. vec3s are 16 byte aligned in uniforms, so the two
+// scalars fill the gaps after them.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    // The direction the light travels in, normalized
+    pub direction: [f32; 3],
+    // Non-zero to apply Reinhard tone mapping after the exposure. Off by
+    // default since it darkens the whole scene, even at an exposure of 1.
+    pub tone_mapping: u32,
+    pub color: [f32; 3],
+    pub exposure: f32,
+}
+
+impl LightUniform {
+    pub fn new(direction: cgmath::Vector3<f32>, color: [f32; 3]) -> Self {
+        use cgmath::InnerSpace;
+        Self {
+            direction: direction.normalize().into(),
+            tone_mapping: 0,
+            color,
+            exposure: 1.0,
+        }
+    }
+}
+
+// How the textured meshes are shaded beyond the light, see
+// State::set_reflectivity, State::set_alpha_cutoff and State::set_pulse.
+// Padded to 16 bytes, the smallest uniform buffer some backends accept.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadingUniform {
+    // How much of the sky is reflected, 0 is only the lit diffuse color
+    // and 1 a perfect mirror
+    pub reflectivity: f32,
+    // Fragments whose texture alpha is below this are discarded, 0 keeps
+    // every fragment
+    pub alpha_cutoff: f32,
+    // How far the brightness swings with time, 0 keeps it steady
+    pub pulse: f32,
+    _padding: f32,
+}
+
+impl Default for ShadingUniform {
+    fn default() -> Self {
+        Self {
+            reflectivity: 0.0,
+            alpha_cutoff: 0.0,
+            pulse: 0.0,
+            _padding: 0.0,
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::texture::{self, TextureFilter};
+
+// What a mesh is drawn with in place of State's active texture: its own
+// texture, bound at group 0 of the mesh pipeline. Meshes share a material
+// through an Rc, see Mesh::material.
+pub struct Material {
+    pub bind_group: wgpu::BindGroup,
+    pub texture: texture::Texture,
+}
+
+// Sets a Material's bind group up with the same entries as State's
+// textures, so it fits the texture layout of the mesh pipeline. The filter
+// has to be the one that layout was created for, see TextureFilter.
+#[derive(Default)]
+pub struct MaterialBuilder<'a> {
+    label: Option<&'a str>,
+    texture: Option<texture::Texture>,
+    filter: TextureFilter,
+}
+
+impl<'a> MaterialBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(&mut self, label: &'a str) -> &mut Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn texture(&mut self, texture: texture::Texture) -> &mut Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    pub fn filter(&mut self, filter: TextureFilter) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
+    // Moves the texture into the Material, so the builder can only build once.
+    pub fn build(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> Material {
+        let texture = self.texture.take().expect("No material texture supplied");
+
+        let mut builder = crate::texture_bind_group_builder(&texture, self.filter);
+        if let Some(label) = self.label {
+            builder.label(label);
+        }
+        let bind_group = builder.build_bind_group(device, layout);
+
+        Material { bind_group, texture }
+    }
+}
@@ -0,0 +1,222 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use cgmath::Point3;
+use wgpu::util::DeviceExt;
+
+use crate::camera::Plane;
+use crate::material::Material;
+
+// Vertex types whose position Mesh can read, for its bounding box
+pub trait VertexPosition {
+    fn position(&self) -> [f32; 3];
+}
+
+// An axis-aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    // Empty, min above max, when there are no vertices
+    pub fn from_vertices<V: VertexPosition>(vertices: &[V]) -> Self {
+        Self::from_points(vertices.iter().map(|vertex| Point3::from(vertex.position())))
+    }
+
+    fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        let empty = Self {
+            min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        };
+        points.into_iter().fold(empty, |aabb, point| Self {
+            min: Point3::new(aabb.min.x.min(point.x), aabb.min.y.min(point.y), aabb.min.z.min(point.z)),
+            max: Point3::new(aabb.max.x.max(point.x), aabb.max.y.max(point.y), aabb.max.z.max(point.z)),
+        })
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self::from_points([self.min, self.max, other.min, other.max])
+    }
+
+    // The box around this one's corners after `transform`, e.g. an
+    // instance's model matrix. Rotations make it bigger than a box fitted
+    // to the transformed vertices would be.
+    pub fn transformed(&self, transform: &cgmath::Matrix4<f32>) -> Self {
+        use cgmath::Transform;
+
+        let corners = (0..8).map(|i| Point3::new(
+            if i & 1 == 0 { self.min.x } else { self.max.x },
+            if i & 2 == 0 { self.min.y } else { self.max.y },
+            if i & 4 == 0 { self.min.z } else { self.max.z },
+        ));
+        Self::from_points(corners.map(|corner| transform.transform_point(corner)))
+    }
+
+    // Whether the whole box is on the outer side of one of the planes,
+    // see Camera::frustum_planes. Conservative: a box near a corner of the
+    // frustum can be outside without this noticing.
+    pub fn is_outside(&self, planes: &[Plane]) -> bool {
+        planes.iter().any(|plane| {
+            // The corner furthest along the normal
+            let corner = Point3::new(
+                if plane.normal.x >= 0.0 { self.max.x } else { self.min.x },
+                if plane.normal.y >= 0.0 { self.max.y } else { self.min.y },
+                if plane.normal.z >= 0.0 { self.max.z } else { self.min.z },
+            );
+            plane.distance(corner) < 0.0
+        })
+    }
+}
+
+// The buffers making up one drawable object, plus what draw_indexed needs
+// to know about them. The vertex type is only needed to fill the buffer, so
+// the same Mesh holds crate::Vertex or crate::ColorVertex data.
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_indices: u32,
+    // Drawn by draw_points_instanced
+    pub num_vertices: u32,
+    pub index_format: wgpu::IndexFormat,
+    // Around the vertices in model space, kept up to date by update_vertices
+    pub aabb: Aabb,
+    // Bound instead of State's active texture when the mesh is drawn
+    // textured. None for meshes that use the active texture, and for
+    // vertex colored ones, which have no texture to bind.
+    pub material: Option<Rc<Material>>,
+    // How many vertices fit in vertex_buffer when rewriting it, zero when it
+    // was created without COPY_DST
+    vertex_capacity: usize,
+    label: String,
+}
+
+impl Mesh {
+    pub fn from_vertices_with_label<V: bytemuck::Pod + VertexPosition>(
+        device: &wgpu::Device,
+        vertices: &[V],
+        indices: &[u32],
+        label: &str,
+    ) -> Self {
+        Self::build(device, vertices, indices, label, false)
+    }
+
+    // A mesh whose vertices can be replaced with update_vertices, e.g. for
+    // geometry animated on the CPU
+    pub fn from_vertices_dynamic<V: bytemuck::Pod + VertexPosition>(
+        device: &wgpu::Device,
+        vertices: &[V],
+        indices: &[u32],
+        label: &str,
+    ) -> Self {
+        Self::build(device, vertices, indices, label, true)
+    }
+
+    fn build<V: bytemuck::Pod + VertexPosition>(
+        device: &wgpu::Device,
+        vertices: &[V],
+        indices: &[u32],
+        label: &str,
+        dynamic: bool,
+    ) -> Self {
+        let usage = if dynamic {
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST
+        } else {
+            wgpu::BufferUsages::VERTEX
+        };
+        let vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Vertex Buffer", label)),
+                contents: bytemuck::cast_slice(vertices),
+                usage,
+            }
+        );
+        let index_format = index_format(vertices.len());
+        let index_bytes = match index_format {
+            wgpu::IndexFormat::Uint32 => bytemuck::cast_slice(indices).to_vec(),
+            wgpu::IndexFormat::Uint16 => {
+                let indices: Vec<u16> = indices.iter().map(|&index| index as u16).collect();
+                bytemuck::cast_slice(&indices).to_vec()
+            }
+        };
+        let index_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Index Buffer", label)),
+                contents: &index_bytes,
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+            num_vertices: vertices.len() as u32,
+            index_format,
+            aabb: Aabb::from_vertices(vertices),
+            material: None,
+            vertex_capacity: if dynamic { vertices.len() } else { 0 },
+            label: label.to_string(),
+        }
+    }
+
+    // Replaces the vertices in place, only reallocating the buffer when they
+    // don't fit. The indices are left alone, so they have to stay valid for
+    // the new vertices, and be of the type the mesh was created with. Static
+    // meshes get a writable buffer on the first call.
+    pub fn update_vertices<V: bytemuck::Pod + VertexPosition>(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, vertices: &[V]) {
+        if vertices.len() > self.vertex_capacity {
+            // Grow geometrically so a slowly growing mesh doesn't reallocate every frame
+            let capacity = vertices.len().max(self.vertex_capacity * 2);
+            self.vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("{} Vertex Buffer", self.label)),
+                size: (capacity * std::mem::size_of::<V>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            self.vertex_capacity = capacity;
+        }
+
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+        self.num_vertices = vertices.len() as u32;
+        self.aabb = Aabb::from_vertices(vertices);
+    }
+
+    // Any per-instance data has to be bound to vertex buffer slot 1 already
+    pub fn draw_instanced<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instances: Range<u32>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), self.index_format);
+        render_pass.draw_indexed(0..self.num_indices, 0, instances);
+    }
+
+    // For PointList pipelines: every vertex once, without the index buffer,
+    // whose grouping into triangles means nothing for points
+    pub fn draw_points_instanced<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instances: Range<u32>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, instances);
+    }
+}
+
+// u16 indices take half the memory, but can only address 65,536 vertices,
+// indices 0 through u16::MAX
+fn index_format(vertex_count: usize) -> wgpu::IndexFormat {
+    if vertex_count > u16::MAX as usize + 1 {
+        wgpu::IndexFormat::Uint32
+    } else {
+        wgpu::IndexFormat::Uint16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_format_switches_past_u16() {
+        assert_eq!(index_format(3), wgpu::IndexFormat::Uint16);
+        // The last vertex is index 65535, which still fits
+        assert_eq!(index_format(65_536), wgpu::IndexFormat::Uint16);
+        assert_eq!(index_format(65_537), wgpu::IndexFormat::Uint32);
+    }
+}
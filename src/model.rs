@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::*;
+
+use crate::mesh::Mesh;
+use crate::Vertex;
+
+pub struct Model {
+    pub mesh: Mesh,
+}
+
+impl Model {
+    pub fn load_obj<P: AsRef<Path>>(device: &wgpu::Device, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        let (vertices, indices) = parse_obj(&source)?;
+
+        let label = path.to_string_lossy();
+        let mesh = Mesh::from_vertices_with_label(device, &vertices, &indices, &label);
+
+        Ok(Self { mesh })
+    }
+}
+
+/// Parses the geometry of a Wavefront OBJ file into a single vertex list.
+///
+/// OBJ faces index positions, texture coordinates and normals separately,
+/// so every distinct combination becomes its own vertex. Polygons are
+/// triangulated as fans and missing uvs or normals are zeroed.
+pub fn parse_obj(source: &str) -> Result<(Vec<Vertex>, Vec<u32>)> {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut lookup: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+
+    for (line_number, line) in source.lines().enumerate() {
+        let line_number = line_number + 1;
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("v") => positions.push(parse_floats(tokens, line_number)?),
+            Some("vt") => {
+                let [u, v]: [f32; 2] = parse_floats(tokens, line_number)?;
+                // OBJ puts the origin of the uv space in the bottom left,
+                // wgpu puts it in the top left.
+                tex_coords.push([u, 1.0 - v]);
+            }
+            Some("vn") => normals.push(parse_floats(tokens, line_number)?),
+            Some("f") => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let position = parts.next()
+                        .map(|index| resolve_index(index, positions.len(), line_number))
+                        .transpose()?
+                        .ok_or_else(|| anyhow!("Missing position index on line {}", line_number))?;
+                    let tex_coord = match parts.next() {
+                        Some("") | None => None,
+                        Some(index) => Some(resolve_index(index, tex_coords.len(), line_number)?),
+                    };
+                    let normal = match parts.next() {
+                        Some("") | None => None,
+                        Some(index) => Some(resolve_index(index, normals.len(), line_number)?),
+                    };
+
+                    let index = *lookup.entry((position, tex_coord, normal)).or_insert_with(|| {
+                        vertices.push(Vertex {
+                            position: positions[position],
+                            tex_coords: tex_coord.map_or([0.0, 0.0], |i| tex_coords[i]),
+                            normal: normal.map_or([0.0, 0.0, 0.0], |i| normals[i]),
+                        });
+                        vertices.len() as u32 - 1
+                    });
+                    face.push(index);
+                }
+
+                if face.len() < 3 {
+                    bail!("Face with fewer than 3 vertices on line {}", line_number);
+                }
+
+                for i in 1..face.len() - 1 {
+                    indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                }
+            }
+            // Groups, materials and comments are ignored
+            _ => {}
+        }
+    }
+
+    Ok((vertices, indices))
+}
+
+fn parse_floats<'a, const N: usize>(mut tokens: impl Iterator<Item = &'a str>, line_number: usize) -> Result<[f32; N]> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        *value = tokens.next()
+            .ok_or_else(|| anyhow!("Expected {} values on line {}", N, line_number))?
+            .parse()
+            .with_context(|| format!("Invalid number on line {}", line_number))?;
+    }
+
+    Ok(values)
+}
+
+/// Converts a 1-based (or negative, relative) OBJ index into a 0-based one.
+fn resolve_index(index: &str, len: usize, line_number: usize) -> Result<usize> {
+    let index: i64 = index.parse()
+        .with_context(|| format!("Invalid index on line {}", line_number))?;
+    let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+
+    if resolved < 0 || resolved >= len as i64 {
+        bail!("Index {} out of range on line {}", index, line_number);
+    }
+
+    Ok(resolved as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cube_has_a_vertex_per_face_corner() {
+        let (vertices, indices) = parse_obj(include_str!("../res/cube.obj")).unwrap();
+        // 6 quads split into 2 triangles each
+        assert_eq!(indices.len(), 36);
+        // The 8 positions and 4 uvs are indexed separately, and every
+        // face corner has its own normal
+        assert_eq!(vertices.len(), 24);
+    }
+
+    #[test]
+    fn shares_vertices_between_faces() {
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 3 4\n";
+        let (vertices, indices) = parse_obj(source).unwrap();
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(vertices.len(), 4);
+        // Position only, so the rest is zeroed
+        assert_eq!(vertices[2].tex_coords, [0.0, 0.0]);
+        assert_eq!(vertices[2].normal, [0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,278 @@
+use wgpu::util::DeviceExt;
+
+use crate::bind_group::BindGroupBuilder;
+use crate::compute::ComputePipelineBuilder;
+use crate::texture;
+
+// Spawning past this reuses the oldest particles
+const MAX_PARTICLES: usize = 4096;
+// Has to match @workgroup_size in particles_compute.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+const GRAVITY: [f32; 3] = [0.0, -2.0, 0.0];
+
+// Matches Particle in both particle shaders. The padding keeps velocity
+// on the 16 byte boundary a storage buffer's vec3 needs.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Particle {
+    position: [f32; 3],
+    // Seconds left, dead at zero or below
+    life: f32,
+    velocity: [f32; 3],
+    _padding: f32,
+}
+
+impl Particle {
+    // Only position and life are needed to draw one
+    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        0 => Float32x3, // Position
+        1 => Float32, // Life
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SimParams {
+    gravity: [f32; 3],
+    dt: f32,
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    reverse_z: bool,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::include_wgsl!("particles.wgsl"));
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Particle Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[Particle::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        // Hidden behind the scene, but see-through, so they don't write depth
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: crate::depth_compare(wgpu::CompareFunction::LessEqual, reverse_z),
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        // Drawn in the scene pass, so this has to match its attachments
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// A fountain of particles simulated by a compute shader. The particles
+// live in one buffer the compute pass updates in place and the render
+// pass reads as instances, so they never go through the CPU after being
+// spawned.
+pub struct Particles {
+    buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    compute_bind_group: wgpu::BindGroup,
+    compute_pipeline: wgpu::ComputePipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    reverse_z: bool,
+    // Where the next spawned particle goes, wrapping around
+    next: usize,
+    // How many slots have ever been spawned into, the rest of the buffer
+    // is skipped by simulate and draw
+    used: u32,
+    // State of the generator spawn picks velocities with
+    rng: u32,
+}
+
+impl Particles {
+    // Needs compute shaders and storage buffers, which WebGL doesn't have
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && device.limits().max_storage_buffers_per_shader_stage > 0
+    }
+
+    // `camera_layout` is the layout of the camera uniform, bound at group 0.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        // All dead to start with
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: (MAX_PARTICLES * std::mem::size_of::<Particle>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Params Buffer"),
+            contents: bytemuck::cast_slice(&[SimParams { gravity: GRAVITY, dt: 0.0 }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (compute_layout, compute_bind_group) = BindGroupBuilder::new()
+            .label("Particle Compute Bind Group")
+            .uniform(0, wgpu::ShaderStages::COMPUTE, &params_buffer)
+            .entry(
+                1,
+                wgpu::ShaderStages::COMPUTE,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                buffer.as_entire_binding(),
+            )
+            .build(device);
+        let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Compute Pipeline Layout"),
+            bind_group_layouts: &[&compute_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = ComputePipelineBuilder::new()
+            .label("Particle Compute Pipeline")
+            .layout(&compute_pipeline_layout)
+            .shader(wgpu::include_wgsl!("particles_compute.wgsl"))
+            .entry_point("cs_main")
+            .build(device);
+
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Render Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = create_render_pipeline(device, &render_pipeline_layout, format, sample_count, false);
+
+        Self {
+            buffer,
+            params_buffer,
+            compute_bind_group,
+            compute_pipeline,
+            render_pipeline_layout,
+            render_pipeline,
+            format,
+            sample_count,
+            reverse_z: false,
+            next: 0,
+            used: 0,
+            rng: 0x9e37_79b9,
+        }
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.render_pipeline = create_render_pipeline(device, &self.render_pipeline_layout, self.format, sample_count, self.reverse_z);
+    }
+
+    // Has to match the camera, see Camera::reverse_z
+    pub fn set_reverse_z(&mut self, device: &wgpu::Device, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+        self.render_pipeline = create_render_pipeline(device, &self.render_pipeline_layout, self.format, self.sample_count, reverse_z);
+    }
+
+    // xorshift32, plenty for scattering particles
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+
+    // Shoots `count` particles up from `origin` in a narrow cone. Once
+    // MAX_PARTICLES are alive the oldest ones are replaced.
+    pub fn spawn(&mut self, queue: &wgpu::Queue, origin: cgmath::Point3<f32>, count: usize) {
+        let count = count.min(MAX_PARTICLES);
+        let particles: Vec<Particle> = (0..count)
+            .map(|_| {
+                let angle = self.random() * std::f32::consts::TAU;
+                let spread = self.random() * 0.6;
+                Particle {
+                    position: origin.into(),
+                    life: 1.5 + self.random() * 1.5,
+                    velocity: [angle.cos() * spread, 2.0 + self.random(), angle.sin() * spread],
+                    _padding: 0.0,
+                }
+            })
+            .collect();
+
+        // Written in two parts when the range wraps past the end
+        let first = count.min(MAX_PARTICLES - self.next);
+        let stride = std::mem::size_of::<Particle>() as wgpu::BufferAddress;
+        queue.write_buffer(&self.buffer, self.next as wgpu::BufferAddress * stride, bytemuck::cast_slice(&particles[..first]));
+        if first < count {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&particles[first..]));
+        }
+        self.used = self.used.max((self.next + count).min(MAX_PARTICLES) as u32);
+        self.next = (self.next + count) % MAX_PARTICLES;
+    }
+
+    // Sets how far the next simulate steps the particles
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) {
+        let params = SimParams {
+            gravity: GRAVITY,
+            dt: dt.as_secs_f32(),
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    // Records the compute pass moving the particles. Has to come before the
+    // pass drawing them.
+    pub fn simulate(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.used == 0 {
+            return;
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle Compute Pass"),
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        // Slots past `used` are dead, so rounding up to whole workgroups is harmless
+        compute_pass.dispatch_workgroups(self.used.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    // Records the particles into a pass that has the scene's depth buffer
+    // attached.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup, camera_offset: wgpu::DynamicOffset) {
+        if self.used == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[camera_offset]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..4, 0..self.used);
+    }
+}
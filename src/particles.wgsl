@@ -0,0 +1,59 @@
+// Draws each particle as a round, screen-aligned quad that fades out as
+// the particle dies. The particles are the instances, four strip vertices
+// each.
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct InstanceInput {
+    @location(0) position: vec3<f32>,
+    @location(1) life: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) life: f32,
+};
+
+// Half the quad's size in clip space, so it shrinks with distance
+let PARTICLE_SIZE: f32 = 0.03;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    particle: InstanceInput,
+) -> VertexOutput {
+    // (-1, -1), (1, -1), (-1, 1), (1, 1)
+    let corner = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u)) * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.corner = corner;
+    out.life = particle.life;
+    if (particle.life <= 0.0) {
+        // Outside the clip volume, so dead particles get clipped away
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    var clip_position = camera.view_proj * vec4<f32>(particle.position, 1.0);
+    clip_position = vec4<f32>(clip_position.xy + corner * PARTICLE_SIZE, clip_position.zw);
+    out.clip_position = clip_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (dot(in.corner, in.corner) > 1.0) {
+        discard;
+    }
+
+    // Fades over the last second
+    let alpha = clamp(in.life, 0.0, 1.0);
+    return vec4<f32>(1.0, 0.6, 0.1, alpha);
+}
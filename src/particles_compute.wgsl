@@ -0,0 +1,38 @@
+// Moves every live particle forward by one frame. Dead particles (life
+// at or below zero) are left alone until they're respawned.
+
+struct Particle {
+    position: vec3<f32>,
+    life: f32,
+    velocity: vec3<f32>,
+    padding: f32,
+};
+
+struct SimParams {
+    gravity: vec3<f32>,
+    dt: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: SimParams;
+
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= arrayLength(&particles)) {
+        return;
+    }
+
+    var particle = particles[index];
+    if (particle.life <= 0.0) {
+        return;
+    }
+
+    particle.velocity = particle.velocity + params.gravity * params.dt;
+    particle.position = particle.position + particle.velocity * params.dt;
+    particle.life = particle.life - params.dt;
+    particles[index] = particle;
+}
@@ -0,0 +1,248 @@
+use crate::texture;
+
+// Rgba8Uint holds the id's four bytes exactly, a normalized format would
+// turn them into floats
+const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Uint;
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    vertex_layout: wgpu::VertexBufferLayout,
+    reverse_z: bool,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Picking Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            // Only the position is read from the vertices
+            buffers: &[vertex_layout, crate::InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: ID_FORMAT,
+                // Integer targets can't be blended
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        // Culled like the scene so the same faces are hit
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: crate::depth_compare(wgpu::CompareFunction::Less, reverse_z),
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        // Ids can't be averaged, so never multisampled
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn create_id_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Picking Id Texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ID_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    })
+}
+
+// Finds the object under a pixel by drawing every instance's id into an
+// offscreen texture and reading the pixel back. Nothing is drawn until
+// pick is called, so it costs nothing while unused.
+pub struct Picker {
+    // One pipeline per vertex type, since the stride is part of the pipeline
+    pipeline: wgpu::RenderPipeline,
+    color_pipeline: wgpu::RenderPipeline,
+    // Kept to rebuild the pipelines in set_reverse_z
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    reverse_z: bool,
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    // id_texture's width and height
+    size: (u32, u32),
+    depth_texture: texture::Texture,
+    // A single pixel is read back at a time
+    readback_buffer: wgpu::Buffer,
+}
+
+impl Picker {
+    // `camera_layout` is the layout of the camera uniform, bound at group 0.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("picking.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Picking Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+
+        let id_texture = create_id_texture(device, config);
+
+        Self {
+            pipeline: create_pipeline(device, &pipeline_layout, &shader, crate::Vertex::desc(), false),
+            color_pipeline: create_pipeline(device, &pipeline_layout, &shader, crate::ColorVertex::desc(), false),
+            shader,
+            pipeline_layout,
+            reverse_z: false,
+            id_view: id_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            id_texture,
+            size: (config.width, config.height),
+            depth_texture: texture::Texture::create_depth_texture(device, config, 1, "picking_depth_texture"),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Picking Readback Buffer"),
+                size: 4,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+        }
+    }
+
+    // The targets have to match the window, so this follows State::resize
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.id_texture = create_id_texture(device, config);
+        self.id_view = self.id_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.size = (config.width, config.height);
+        self.depth_texture = texture::Texture::create_depth_texture(device, config, 1, "picking_depth_texture");
+    }
+
+    // Has to match the camera, see Camera::reverse_z
+    pub fn set_reverse_z(&mut self, device: &wgpu::Device, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, crate::Vertex::desc(), reverse_z);
+        self.color_pipeline = create_pipeline(device, &self.pipeline_layout, &self.shader, crate::ColorVertex::desc(), reverse_z);
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x < self.size.0 && y < self.size.1
+    }
+
+    // Starts the pass drawing the ids, with the pipeline and camera bound.
+    // The caller binds the instance buffer and records the instanced draws,
+    // `color_vertices` choosing the pipeline for ColorVertex meshes. Only
+    // (x, y) is rasterized.
+    pub fn begin_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        camera_bind_group: &'a wgpu::BindGroup,
+        camera_offset: wgpu::DynamicOffset,
+        color_vertices: bool,
+        x: u32,
+        y: u32,
+    ) -> wgpu::RenderPass<'a> {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Picking Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.id_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Zero is "nothing here"
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(crate::depth_clear_value(self.reverse_z)),
+                    store: true,
+                }),
+                // The depth format has a stencil aspect, which picking doesn't use
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: false,
+                }),
+            }),
+        });
+
+        render_pass.set_scissor_rect(x, y, 1, 1);
+        render_pass.set_pipeline(if color_vertices { &self.color_pipeline } else { &self.pipeline });
+        render_pass.set_bind_group(0, camera_bind_group, &[camera_offset]);
+        render_pass
+    }
+
+    // Submits `encoder`, which has to contain a finished begin_pass pass
+    // for the same (x, y), and returns the instance index drawn there.
+    // Blocks until the GPU is done, so it's meant for clicks rather than
+    // every frame. On the web the readback can't be waited for and this
+    // returns None.
+    pub fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mut encoder: wgpu::CommandEncoder,
+        x: u32,
+        y: u32,
+    ) -> Option<u32> {
+        // Texture rows start at the top like winit's coordinates, the flip
+        // from clip space's upwards y already happened when rasterizing, so
+        // (x, y) is used as is
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                // The row is a single pixel, but the stride still has to be
+                // aligned. Only the first 4 bytes get written.
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+
+        // Empty when the map hasn't finished, which is always the case on the web
+        let mapped = matches!(receiver.try_recv(), Ok(Ok(())));
+        let id = mapped.then(|| {
+            let data = slice.get_mapped_range();
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+        });
+        // Unmapping also cancels a map that's still pending, so the next
+        // pick can map again
+        self.readback_buffer.unmap();
+
+        // Ids are instance indices plus one
+        id.and_then(|id| id.checked_sub(1))
+    }
+}
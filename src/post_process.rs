@@ -0,0 +1,178 @@
+use crate::bind_group::BindGroupBuilder;
+use crate::texture;
+use crate::uniform::Uniform;
+
+// The effects built into post_process.wgsl
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    None,
+    Grayscale,
+    Invert,
+    ScanlineCRT,
+}
+
+impl PostEffect {
+    // For cycling through the effects with a key
+    pub fn next(self) -> Self {
+        match self {
+            PostEffect::None => PostEffect::Grayscale,
+            PostEffect::Grayscale => PostEffect::Invert,
+            PostEffect::Invert => PostEffect::ScanlineCRT,
+            PostEffect::ScanlineCRT => PostEffect::None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EffectUniform {
+    // Index into the switch in post_process.wgsl
+    kind: u32,
+    // Uniform buffers have to be a multiple of 16 bytes on WebGL
+    _pad: [u32; 3],
+}
+
+impl EffectUniform {
+    fn new(effect: PostEffect) -> Self {
+        let kind = match effect {
+            PostEffect::None => 0,
+            PostEffect::Grayscale => 1,
+            PostEffect::Invert => 2,
+            PostEffect::ScanlineCRT => 3,
+        };
+
+        Self { kind, _pad: [0; 3] }
+    }
+}
+
+fn bind_group_builder(source: &texture::Texture) -> BindGroupBuilder<'_> {
+    let mut builder = BindGroupBuilder::new();
+    builder
+        .label("post_process_bind_group")
+        .texture(0, wgpu::ShaderStages::FRAGMENT, &source.view)
+        .sampler(1, wgpu::ShaderStages::FRAGMENT, &source.sampler);
+    builder
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Post Process Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            // The fullscreen triangle is generated from the vertex index
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Draws a texture, usually the rendered scene, over the whole target
+// through a replaceable shader. The shader needs a vs_main taking the
+// vertex index and an fs_main sampling group 0, see post_process.wgsl.
+pub struct PostProcess {
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    effect: PostEffect,
+    effect_uniform: Uniform<EffectUniform>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, source: &texture::Texture) -> Self {
+        let (bind_group_layout, bind_group) = bind_group_builder(source).build(device);
+        let effect = PostEffect::None;
+        let effect_uniform = Uniform::new(device, EffectUniform::new(effect));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, effect_uniform.layout()],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("post_process.wgsl"));
+        let pipeline = create_pipeline(device, &pipeline_layout, format, &shader);
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            effect,
+            effect_uniform,
+            pipeline_layout,
+            pipeline,
+            format,
+        }
+    }
+
+    // Has to be called whenever the source texture is recreated, e.g. on resize.
+    pub fn set_source(&mut self, device: &wgpu::Device, source: &texture::Texture) {
+        self.bind_group = bind_group_builder(source).build_bind_group(device, &self.bind_group_layout);
+    }
+
+    pub fn effect(&self) -> PostEffect {
+        self.effect
+    }
+
+    // Only has a visible effect with shaders that read the effect uniform,
+    // like the default one.
+    pub fn set_effect(&mut self, queue: &wgpu::Queue, effect: PostEffect) {
+        self.effect = effect;
+        self.effect_uniform.update(queue, EffectUniform::new(effect));
+    }
+
+    // Swaps in another post-processing shader. If it doesn't compile, or
+    // doesn't match the expected entry points and bindings, the current
+    // one is kept and the error returned.
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader: wgpu::ShaderModuleDescriptor) -> Result<(), wgpu::Error> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(shader);
+        let pipeline = create_pipeline(device, &self.pipeline_layout, self.format, &shader);
+
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(e) => Err(e),
+            None => {
+                self.pipeline = pipeline;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Process Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten, so there's nothing to clear
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, self.effect_uniform.bind_group(), &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
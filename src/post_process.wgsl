@@ -0,0 +1,62 @@
+// Draws the scene with one of the built-in effects. Custom post-processing
+// shaders need the same vs_main / fs_main entry points and group 0
+// bindings; the effect uniform in group 1 is optional.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// A single triangle that covers the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+// Matches PostEffect on the Rust side
+struct Effect {
+    kind: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> effect: Effect;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSample(t_scene, s_scene, in.tex_coords);
+
+    switch effect.kind {
+        // Grayscale, using Rec. 709 luma weights since the scene is linear
+        case 1u: {
+            let luminance = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+            color = vec4<f32>(vec3<f32>(luminance), color.a);
+        }
+        // Invert
+        case 2u: {
+            color = vec4<f32>(1.0 - color.rgb, color.a);
+        }
+        // Scanline CRT: darken every third row and the corners
+        case 3u: {
+            let scanline = select(1.0, 0.5, u32(in.clip_position.y) % 3u == 0u);
+            let from_center = in.tex_coords - vec2<f32>(0.5, 0.5);
+            let vignette = clamp(1.0 - dot(from_center, from_center) * 1.5, 0.0, 1.0);
+            color = vec4<f32>(color.rgb * scanline * vignette, color.a);
+        }
+        default: {}
+    }
+
+    return color;
+}
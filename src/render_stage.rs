@@ -0,0 +1,213 @@
+use crate::State;
+
+// One step of a frame. State::render runs its stages in order, each one
+// recording its own passes into the frame's encoder, so passes can be
+// added or reordered without touching render itself.
+pub trait RenderPassStage {
+    // `view` is the frame's final output. Stages that draw the scene target
+    // State::scene_attachment instead, which PostProcessStage then draws
+    // into `view`.
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, state: &State);
+}
+
+// The stages State starts out with, in the order they run
+pub fn default_stages() -> Vec<Box<dyn RenderPassStage>> {
+    vec![
+        Box::new(ClearStage),
+        Box::new(SkyboxStage),
+        Box::new(GeometryStage),
+        Box::new(RenderTargetsStage),
+        Box::new(PostProcessStage),
+        Box::new(SpriteStage),
+        Box::new(OverlayStage),
+    ]
+}
+
+// Clears the scene to the clear color, unless State::clear_enabled is off,
+// and resets the depth and stencil buffers
+pub struct ClearStage;
+
+impl RenderPassStage for ClearStage {
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _view: &wgpu::TextureView, state: &State) {
+        let (view, resolve_target) = state.scene_attachment();
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    // Keeping the previous frame leaves trails behind anything that moves
+                    load: if state.clear_enabled {
+                        wgpu::LoadOp::Clear(state.clear_color)
+                    } else {
+                        wgpu::LoadOp::Load
+                    },
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: state.framebuffer.depth_view(),
+                // Depth is cleared either way, otherwise last frame's depth
+                // would hide this frame's geometry
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(crate::depth_clear_value(state.camera.reverse_z)),
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: true,
+                }),
+            }),
+        });
+    }
+}
+
+// Draws the sky over the clear color when it's enabled
+pub struct SkyboxStage;
+
+impl RenderPassStage for SkyboxStage {
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _view: &wgpu::TextureView, state: &State) {
+        if state.show_skybox {
+            // The sky doesn't resolve, a later scene pass takes care of that
+            let (view, _) = state.scene_attachment();
+            state.skybox.draw(encoder, view);
+        }
+    }
+}
+
+// The meshes and the debug lines, depth tested against each other. The
+// meshes are the textured ones, or the vertex colored ones when
+// State::use_vertex_colors is set. Textured meshes are drawn in a solid
+// color instead when State::set_untextured is on.
+pub struct GeometryStage;
+
+impl RenderPassStage for GeometryStage {
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _view: &wgpu::TextureView, state: &State) {
+        let (view, resolve_target) = state.scene_attachment();
+
+        // Borrowed before the pass starts so it outlives the buffers the pass holds on to
+        let meshes = if state.use_vertex_colors {
+            state.color_meshes.borrow()
+        } else {
+            state.meshes.borrow()
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: state.framebuffer.depth_view(),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+            }),
+        });
+
+        if state.use_vertex_colors {
+            render_pass.set_pipeline(&state.color_pipeline);
+
+            render_pass.set_bind_group(0, state.camera_uniform.bind_group(), &[state.camera_offset()]);
+            render_pass.set_bind_group(1, state.light_uniform.bind_group(), &[]);
+        } else if state.untextured {
+            render_pass.set_pipeline(&state.untextured_pipeline);
+
+            render_pass.set_bind_group(0, state.camera_uniform.bind_group(), &[state.camera_offset()]);
+        } else {
+            // Group 0 is the texture, which can differ per mesh
+            render_pass.set_pipeline(&state.render_pipeline);
+
+            render_pass.set_bind_group(1, state.camera_uniform.bind_group(), &[state.camera_offset()]);
+            render_pass.set_bind_group(2, state.light_uniform.bind_group(), &[]);
+            render_pass.set_bind_group(3, &state.shading_bind_group, &[]);
+        }
+        render_pass.set_stencil_reference(state.stencil_reference);
+        render_pass.set_vertex_buffer(1, state.instance_buffer.slice(..));
+
+        // Off screen meshes were found in State::update
+        let instances = 0..state.instances.len() as u32;
+        let textured = !state.use_vertex_colors && !state.untextured;
+        for (index, mesh) in meshes.iter().enumerate() {
+            if !state.is_mesh_visible(index) {
+                continue;
+            }
+            if textured {
+                render_pass.set_bind_group(0, state.mesh_bind_group(mesh), &[]);
+            }
+            if state.topology == wgpu::PrimitiveTopology::PointList {
+                mesh.draw_points_instanced(&mut render_pass, instances.clone());
+            } else {
+                mesh.draw_instanced(&mut render_pass, instances.clone());
+            }
+        }
+
+        state.debug_lines.draw(&mut render_pass, state.camera_uniform.bind_group(), state.camera_offset());
+        // Blended, so after everything opaque
+        if let Some(particles) = &state.particles {
+            particles.draw(&mut render_pass, state.camera_uniform.bind_group(), state.camera_offset());
+        }
+    }
+}
+
+// The textured meshes again, into the targets from State::set_render_targets
+pub struct RenderTargetsStage;
+
+impl RenderPassStage for RenderTargetsStage {
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _view: &wgpu::TextureView, state: &State) {
+        let render_targets = match &state.render_targets {
+            Some(render_targets) => render_targets,
+            None => return,
+        };
+        let meshes = state.meshes.borrow();
+
+        let mut render_pass = render_targets.begin_pass(encoder);
+        render_pass.set_bind_group(1, state.camera_uniform.bind_group(), &[state.camera_offset()]);
+        render_pass.set_bind_group(2, state.light_uniform.bind_group(), &[]);
+        render_pass.set_bind_group(3, &state.shading_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, state.instance_buffer.slice(..));
+        for (index, mesh) in meshes.iter().enumerate() {
+            if state.is_mesh_visible(index) {
+                render_pass.set_bind_group(0, state.mesh_bind_group(mesh), &[]);
+                mesh.draw_instanced(&mut render_pass, 0..state.instances.len() as _);
+            }
+        }
+    }
+}
+
+// Applies the post effect while copying the scene into the output
+pub struct PostProcessStage;
+
+impl RenderPassStage for PostProcessStage {
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, state: &State) {
+        state.post_process.draw(encoder, view);
+    }
+}
+
+// The sprites queued for this frame, drawn over the post processed output
+pub struct SpriteStage;
+
+impl RenderPassStage for SpriteStage {
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, state: &State) {
+        state.sprite_batch.draw(encoder, view, &state.bind_groups);
+    }
+}
+
+// The text prepared for this frame, drawn on top of the output
+pub struct OverlayStage;
+
+impl RenderPassStage for OverlayStage {
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, state: &State) {
+        state.text_renderer.draw(encoder, view);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::texture;
+
+// wgpu doesn't allow more color attachments in one pass
+const MAX_TARGETS: usize = 8;
+
+// The formats gbuffer_shader writes: albedo, world space normal and world
+// space position
+pub const GBUFFER_FORMATS: [wgpu::TextureFormat; 3] = [
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+    wgpu::TextureFormat::Rgba16Float,
+    wgpu::TextureFormat::Rgba16Float,
+];
+
+// A G-buffer shader for State::set_render_targets with GBUFFER_FORMATS
+pub fn gbuffer_shader() -> wgpu::ShaderModuleDescriptor<'static> {
+    wgpu::include_wgsl!("gbuffer.wgsl")
+}
+
+// The @location outputs of a WGSL shader's fs_main, or None when that
+// can't be told, e.g. for other shader languages or ones that don't parse
+fn fragment_locations(shader: &wgpu::ShaderModuleDescriptor) -> Option<Vec<u32>> {
+    let source = match &shader.source {
+        wgpu::ShaderSource::Wgsl(source) => source,
+        #[allow(unreachable_patterns)]
+        _ => return None,
+    };
+    let module = naga::front::wgsl::parse_str(source).ok()?;
+    let entry_point = module
+        .entry_points
+        .iter()
+        .find(|entry_point| entry_point.stage == naga::ShaderStage::Fragment && entry_point.name == "fs_main")?;
+    let result = entry_point.function.result.as_ref()?;
+
+    // Either a single output, or a struct of them
+    let bindings: Vec<_> = match (&result.binding, &module.types[result.ty].inner) {
+        (Some(binding), _) => vec![binding],
+        (None, naga::TypeInner::Struct { members, .. }) => members.iter().filter_map(|member| member.binding.as_ref()).collect(),
+        _ => Vec::new(),
+    };
+    let mut locations: Vec<_> = bindings
+        .into_iter()
+        .filter_map(|binding| match binding {
+            naga::Binding::Location { location, .. } => Some(*location),
+            naga::Binding::BuiltIn(_) => None,
+        })
+        .collect();
+    locations.sort_unstable();
+    Some(locations)
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    formats: &[wgpu::TextureFormat],
+    reverse_z: bool,
+) -> wgpu::RenderPipeline {
+    // One per target, matched to the fragment outputs by @location
+    let targets: Vec<_> = formats
+        .iter()
+        .map(|&format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        })
+        .collect();
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Targets Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[crate::Vertex::desc(), crate::InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: crate::depth_compare(wgpu::CompareFunction::LessEqual, reverse_z),
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Several color textures the textured meshes are drawn into in a single
+// pass, one per @location output of the fragment shader. E.g. the albedo,
+// normal and position textures of a deferred renderer's G-buffer. They
+// have their own depth buffer and aren't multisampled.
+pub struct RenderTargets {
+    formats: Vec<wgpu::TextureFormat>,
+    textures: Vec<texture::Texture>,
+    depth: texture::Texture,
+    shader: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+    reverse_z: bool,
+}
+
+fn create_textures(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    formats: &[wgpu::TextureFormat],
+) -> Vec<texture::Texture> {
+    formats
+        .iter()
+        .enumerate()
+        .map(|(i, &format)| {
+            let config = wgpu::SurfaceConfiguration { format, ..config.clone() };
+            texture::Texture::create_render_target(device, &config, &format!("render_target_{}", i))
+        })
+        .collect()
+}
+
+impl RenderTargets {
+    // `layout` has to be the layout of the mesh pipeline, the shader gets
+    // the same bind groups and vertex inputs as shader.wgsl. Fails when
+    // there are no formats or too many, or when the formats can't be
+    // rendered to or don't line up with the shader's outputs.
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layout: &wgpu::PipelineLayout,
+        formats: &[wgpu::TextureFormat],
+        shader: wgpu::ShaderModuleDescriptor,
+        reverse_z: bool,
+    ) -> anyhow::Result<Self> {
+        if formats.is_empty() || formats.len() > MAX_TARGETS {
+            anyhow::bail!("{} render targets requested, expected 1 to {}", formats.len(), MAX_TARGETS);
+        }
+        // wgpu doesn't check this, extra outputs would be dropped and
+        // targets without one left undefined
+        if let Some(locations) = fragment_locations(&shader) {
+            if !locations.iter().copied().eq(0..formats.len() as u32) {
+                anyhow::bail!(
+                    "fs_main writes @location {:?}, expected one output for each of the {} render targets",
+                    locations,
+                    formats.len(),
+                );
+            }
+        }
+
+        // Mismatches between the targets and the shader would otherwise
+        // panic in the uncaptured error handler
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let textures = create_textures(device, config, formats);
+        let shader = device.create_shader_module(shader);
+        let pipeline = create_pipeline(device, layout, &shader, formats, reverse_z);
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            anyhow::bail!("invalid render targets: {}", e);
+        }
+
+        Ok(Self {
+            formats: formats.to_vec(),
+            textures,
+            depth: texture::Texture::create_depth_texture(device, config, 1, "render_targets_depth"),
+            shader,
+            pipeline,
+            reverse_z,
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.textures = create_textures(device, config, &self.formats);
+        self.depth = texture::Texture::create_depth_texture(device, config, 1, "render_targets_depth");
+    }
+
+    // Has to match the camera, see Camera::reverse_z
+    pub fn set_reverse_z(&mut self, device: &wgpu::Device, layout: &wgpu::PipelineLayout, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+        self.pipeline = create_pipeline(device, layout, &self.shader, &self.formats, reverse_z);
+    }
+
+    pub fn view(&self, index: usize) -> Option<&wgpu::TextureView> {
+        self.textures.get(index).map(|texture| &texture.view)
+    }
+
+    // Starts the pass drawing into every target, with all of them cleared to
+    // transparent black and the depth buffer reset. The pipeline is set, the
+    // caller binds the mesh bind groups and draws.
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        let color_attachments: Vec<_> = self
+            .textures
+            .iter()
+            .map(|texture| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })
+            })
+            .collect();
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Targets Pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(crate::depth_clear_value(self.reverse_z)),
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: false,
+                }),
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass
+    }
+}
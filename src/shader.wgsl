@@ -1,29 +1,69 @@
 // Vertex shader
 
 struct CameraUniform {
+    view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
 };
 
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+struct Light {
+    direction: vec3<f32>,
+    tone_mapping: u32,
+    color: vec3<f32>,
+    exposure: f32,
+};
+
+@group(2) @binding(0)
+var<uniform> light: Light;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
 };
 
 @vertex
 fn vs_main(
     model: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    // Instances are only rotated and translated, so the upper 3x3 of the
+    // model matrix is enough to transform the normal.
+    let normal_matrix = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    );
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.normal = normal_matrix * model.normal;
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
@@ -34,7 +74,108 @@ var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
 
+struct TextureTransform {
+    scale: vec2<f32>,
+    offset: vec2<f32>,
+};
+
+@group(3) @binding(0)
+var<uniform> texture_transform: TextureTransform;
+
+struct Shading {
+    reflectivity: f32,
+    alpha_cutoff: f32,
+    pulse: f32,
+};
+
+@group(3) @binding(1)
+var<uniform> shading: Shading;
+
+// The sky, reflected by the meshes
+@group(3) @binding(2)
+var t_environment: texture_cube<f32>;
+@group(3) @binding(3)
+var s_environment: sampler;
+
+struct Time {
+    // Since the State was created, see State::step
+    seconds: f32,
+};
+
+@group(3) @binding(4)
+var<uniform> time: Time;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let tex_coords = in.tex_coords * texture_transform.scale + texture_transform.offset;
+    let object_color = textureSample(t_diffuse, s_diffuse, tex_coords);
+
+    // A little ambient light so faces turned away from the light aren't black
+    let ambient_strength = 0.1;
+    let ambient_color = light.color * ambient_strength;
+
+    // Blinn-Phong: specular uses the half vector between the light and view directions
+    let normal = normalize(in.normal);
+    let light_dir = normalize(-light.direction);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
+    let specular_color = specular_strength * light.color;
+
+    let lit_color = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+
+    // The view direction mirrored about the normal picks the part of the sky
+    // the surface reflects
+    let reflected_dir = reflect(-view_dir, normal);
+    let reflected_color = textureSample(t_environment, s_environment, reflected_dir).rgb;
+    // A slow pulse in brightness to show off the time uniform, off unless
+    // State::set_pulse gives it an amplitude
+    let pulse = 1.0 + shading.pulse * sin(time.seconds * 2.0);
+    let result = mix(lit_color, reflected_color, shading.reflectivity) * pulse;
+
+    // Exposure, then Reinhard tone mapping to bring bright values back under 1
+    var color = result * light.exposure;
+    if (light.tone_mapping != 0u) {
+        color = color / (color + vec3<f32>(1.0));
+    }
+
+    // Alpha test: transparent parts are cut out rather than blended, so
+    // the rest stays opaque and needs no sorting. Last, so the textures
+    // above are sampled by every fragment.
+    if (object_color.a < shading.alpha_cutoff) {
+        discard;
+    }
+
+    return vec4<f32>(color, object_color.a);
+}
+
+// Untextured variant, see State::set_untextured. Its pipeline layout only
+// has the camera, at group 0, so the meshes are drawn without binding a
+// texture, light or texture transform.
+
+@group(0) @binding(0)
+var<uniform> untextured_camera: CameraUniform;
+
+@vertex
+fn vs_untextured(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return untextured_camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+// Unlit, so every mesh is a flat silhouette in this color
+@fragment
+fn fs_untextured() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.5, 0.5, 0.5, 1.0);
 }
@@ -0,0 +1,142 @@
+use cgmath::SquareMatrix;
+
+use crate::bind_group::BindGroupBuilder;
+use crate::camera::Camera;
+use crate::texture;
+use crate::uniform::Uniform;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxUniform {
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+impl SkyboxUniform {
+    fn new(camera: &Camera) -> Self {
+        // Only the camera's rotation matters for the sky
+        let mut view = camera.build_view_matrix();
+        view.w = cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let inv_view_proj = (camera.build_projection_matrix() * view)
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+
+        Self {
+            inv_view_proj: inv_view_proj.into(),
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::include_wgsl!("skybox.wgsl"));
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Skybox Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            // The fullscreen triangle is generated from the vertex index
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // The sky gets its own pass before the scene, so it never needs depth
+        depth_stencil: None,
+        // Has to match the color attachment, which is the MSAA framebuffer
+        // when multisampling
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// A cubemap drawn behind the scene in place of the clear color.
+pub struct Skybox {
+    bind_group: wgpu::BindGroup,
+    uniform: Uniform<SkyboxUniform>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    format: wgpu::TextureFormat,
+}
+
+impl Skybox {
+    // `cubemap` should come from Texture::from_cubemap.
+    pub fn new(
+        device: &wgpu::Device,
+        cubemap: &texture::Texture,
+        camera: &Camera,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let (bind_group_layout, bind_group) = {
+            let mut builder = BindGroupBuilder::new();
+            builder
+                .label("skybox_bind_group")
+                .cube_texture(0, wgpu::ShaderStages::FRAGMENT, &cubemap.view)
+                .sampler(1, wgpu::ShaderStages::FRAGMENT, &cubemap.sampler);
+            builder.build(device)
+        };
+        let uniform = Uniform::new(device, SkyboxUniform::new(camera));
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, uniform.layout()],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, format, sample_count);
+
+        Self {
+            bind_group,
+            uniform,
+            pipeline_layout,
+            pipeline,
+            format,
+        }
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, self.format, sample_count);
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        self.uniform.update(queue, SkyboxUniform::new(camera));
+    }
+
+    // Fills `view` with the sky. The scene pass afterwards has to load
+    // instead of clear.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Skybox Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten, so there's nothing to clear
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, self.uniform.bind_group(), &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
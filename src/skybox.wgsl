@@ -0,0 +1,40 @@
+// Draws a cubemap behind everything else. Each pixel is turned back into
+// a view direction with the inverse view-projection matrix.
+
+struct SkyboxUniform {
+    // The camera's translation is left out, the sky is infinitely far away
+    inv_view_proj: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> skybox: SkyboxUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) clip_xy: vec2<f32>,
+};
+
+// A single triangle that covers the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let clip_xy = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(clip_xy, 1.0, 1.0);
+    out.clip_xy = clip_xy;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(1)
+var s_sky: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Any point on the far plane works, only the direction matters
+    let world = skybox.inv_view_proj * vec4<f32>(in.clip_xy, 1.0, 1.0);
+    let direction = world.xyz / world.w;
+    return textureSample(t_sky, s_sky, direction);
+}
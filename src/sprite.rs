@@ -0,0 +1,240 @@
+use std::ops::Range;
+
+use crate::uniform::Uniform;
+
+// An axis aligned rectangle, top left corner plus size
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    // The whole texture, as a source rect
+    pub const FULL: Self = Self {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteVertex {
+    // In pixels, origin in the top left
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+    tint: [f32; 4],
+}
+
+impl SpriteVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x2, // Position
+        1 => Float32x2, // Texture coordinate
+        2 => Float32x4, // Tint
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProjectionUniform {
+    proj: [[f32; 4]; 4],
+}
+
+impl ProjectionUniform {
+    // Orthographic, with y pointing down so (0, 0) is the top left pixel
+    fn new(screen_size: winit::dpi::PhysicalSize<u32>) -> Self {
+        let projection = cgmath::ortho(0.0, screen_size.width as f32, screen_size.height as f32, 0.0, -1.0, 1.0);
+        Self {
+            proj: (crate::camera::OPENGL_TO_WGPU_MATRIX * projection).into(),
+        }
+    }
+}
+
+// Sprites using one texture, drawn together
+struct Batch {
+    texture: usize,
+    vertices: Range<u32>,
+}
+
+// Queues textured quads for the current frame and draws them in as few
+// calls as possible: consecutive sprites with the same texture share a
+// draw. Sprites are drawn in the order they were queued, so switching
+// textures back and forth costs a draw call per switch.
+pub struct SpriteBatch {
+    projection: Uniform<ProjectionUniform>,
+    render_pipeline: wgpu::RenderPipeline,
+    vertices: Vec<SpriteVertex>,
+    batches: Vec<Batch>,
+    vertex_buffer: wgpu::Buffer,
+    // Number of vertices the buffer can hold
+    vertex_capacity: usize,
+    // The batches uploaded by the last prepare
+    prepared_batches: Vec<Batch>,
+}
+
+impl SpriteBatch {
+    // `texture_layout` is the layout of the textures' bind groups: the
+    // texture at binding 0 and its sampler at binding 1.
+    pub fn new(device: &wgpu::Device, texture_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat) -> Self {
+        let projection = Uniform::new(device, ProjectionUniform::new(winit::dpi::PhysicalSize::new(1, 1)));
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("sprite.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, projection.layout()],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[SpriteVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Flipped sprites (negative sizes) wind the other way, so nothing is culled
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            // Drawn in order over the scene, later sprites on top
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let vertex_capacity = 6 * 64;
+        let vertex_buffer = Self::create_vertex_buffer(device, vertex_capacity);
+
+        Self {
+            projection,
+            render_pipeline,
+            vertices: Vec::new(),
+            batches: Vec::new(),
+            vertex_buffer,
+            vertex_capacity,
+            prepared_batches: Vec::new(),
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<SpriteVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Queues the `src` part of texture number `texture` stretched over
+    // `dest`. `dest` is in pixels from the top left of the screen, `src`
+    // in texture coordinates, 0 to 1 with the origin in the top left (see
+    // Rect::FULL). The texture's colors are multiplied by `tint`.
+    pub fn queue_sprite(&mut self, texture: usize, dest: Rect, src: Rect, tint: [f32; 4]) {
+        let start = self.vertices.len() as u32;
+
+        let (x0, y0, x1, y1) = (dest.x, dest.y, dest.x + dest.width, dest.y + dest.height);
+        let (u0, v0, u1, v1) = (src.x, src.y, src.x + src.width, src.y + src.height);
+        let vertex = |position: [f32; 2], tex_coords: [f32; 2]| SpriteVertex { position, tex_coords, tint };
+        self.vertices.extend_from_slice(&[
+            vertex([x0, y0], [u0, v0]),
+            vertex([x0, y1], [u0, v1]),
+            vertex([x1, y1], [u1, v1]),
+            vertex([x0, y0], [u0, v0]),
+            vertex([x1, y1], [u1, v1]),
+            vertex([x1, y0], [u1, v0]),
+        ]);
+
+        let end = self.vertices.len() as u32;
+        match self.batches.last_mut() {
+            Some(batch) if batch.texture == texture => batch.vertices.end = end,
+            _ => self.batches.push(Batch {
+                texture,
+                vertices: start..end,
+            }),
+        }
+    }
+
+    // Uploads everything queued since the last call and clears the queue.
+    // Has to happen before the encoder that draws it is submitted.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        screen_size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        self.prepared_batches = std::mem::take(&mut self.batches);
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        self.projection.update(queue, ProjectionUniform::new(screen_size));
+
+        if self.vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = self.vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.vertex_capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+
+        self.vertices.clear();
+    }
+
+    // Draws the sprites from the last prepare into `view`, keeping its
+    // current contents. `textures` are the bind groups the sprites' texture
+    // indices refer to, sprites with an index past the end are skipped.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, textures: &[wgpu::BindGroup]) {
+        if self.prepared_batches.is_empty() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sprite Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Draw over the scene instead of clearing it
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(1, self.projection.bind_group(), &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        for batch in &self.prepared_batches {
+            if let Some(texture) = textures.get(batch.texture) {
+                render_pass.set_bind_group(0, texture, &[]);
+                render_pass.draw(batch.vertices.clone(), 0..1);
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+
+use crate::bind_group::BindGroupBuilder;
+use crate::texture;
+use crate::uniform::Uniform;
+
+// Pixels per em at scale 1
+const FONT_SIZE: f32 = 8.0;
+// Glyphs are rasterized on demand and packed into rows of this square
+// atlas, with a pixel of padding between them so neighbouring glyphs
+// don't bleed into each other.
+const ATLAS_SIZE: u32 = 1024;
+
+// Where a rasterized glyph is in the atlas and how to place it
+#[derive(Copy, Clone, Debug)]
+struct Glyph {
+    // Texture coordinates of the top left and bottom right corners
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    // In pixels, from the pen position on the baseline to the top left
+    // corner, with y down
+    offset: [f32; 2],
+    size: [f32; 2],
+    advance: f32,
+}
+
+// A glyph bitmap waiting for prepare to copy it into the atlas
+struct Upload {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    coverage: Vec<u8>,
+}
+
+// Hands out atlas space row by row. A row is as tall as the tallest glyph
+// placed in it.
+#[derive(Default)]
+struct RowPacker {
+    x: u32,
+    y: u32,
+    row_height: u32,
+}
+
+impl RowPacker {
+    // The top left corner of the space, None when the atlas is full
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if self.x + width + 1 > ATLAS_SIZE {
+            self.x = 0;
+            self.y += self.row_height + 1;
+            self.row_height = 0;
+        }
+        if width + 1 > ATLAS_SIZE || self.y + height + 1 > ATLAS_SIZE {
+            return None;
+        }
+
+        let position = (self.x, self.y);
+        self.x += width + 1;
+        self.row_height = self.row_height.max(height);
+        Some(position)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextVertex {
+    // In pixels, origin in the top left
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+}
+
+impl TextVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x2, // Position
+        1 => Float32x2, // Texture coordinate
+        2 => Float32x4, // Color
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ScreenUniform {
+    size: [f32; 2],
+    // Uniform buffers have to be a multiple of 16 bytes on WebGL
+    _pad: [f32; 2],
+}
+
+// Queues text for the current frame and draws it on top of whatever has
+// already been rendered.
+pub struct TextRenderer {
+    font: fontdue::Font,
+    // By character and font size in pixels, as f32 bits
+    glyphs: HashMap<(char, u32), Glyph>,
+    packer: RowPacker,
+    uploads: Vec<Upload>,
+    // Set when a glyph didn't fit, the atlas is started over after the
+    // frame that needed it is prepared
+    atlas_full: bool,
+    atlas: texture::Texture,
+    atlas_bind_group: wgpu::BindGroup,
+    screen_uniform: Uniform<ScreenUniform>,
+    render_pipeline: wgpu::RenderPipeline,
+    vertices: Vec<TextVertex>,
+    vertex_buffer: wgpu::Buffer,
+    // Number of vertices the buffer can hold
+    vertex_capacity: usize,
+    // Number of vertices uploaded by the last prepare
+    prepared_vertices: u32,
+}
+
+impl TextRenderer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let font = fontdue::Font::from_bytes(
+            include_bytes!("DejaVuSansMono.ttf") as &[u8],
+            fontdue::FontSettings::default(),
+        ).expect("The bundled font is valid");
+
+        // Starts out blank, glyphs are written in as they're first drawn
+        let atlas_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Font Atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let atlas = texture::Texture {
+            view: atlas_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            // Glyphs are rasterized at the size they're drawn at and placed
+            // on whole pixels, so each texel covers exactly one pixel
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }),
+            texture: atlas_texture,
+        };
+
+        let (atlas_bind_group_layout, atlas_bind_group) = BindGroupBuilder::new()
+            .label("font_atlas_bind_group")
+            .texture(0, wgpu::ShaderStages::FRAGMENT, &atlas.view)
+            .sampler(1, wgpu::ShaderStages::FRAGMENT, &atlas.sampler)
+            .build(device);
+
+        let screen_uniform = Uniform::new(device, ScreenUniform {
+            size: [1.0, 1.0],
+            _pad: [0.0; 2],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("text.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[&atlas_bind_group_layout, screen_uniform.layout()],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Text Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[TextVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Text is always drawn on top, so there's no depth testing
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let vertex_capacity = 6 * 64;
+        let vertex_buffer = Self::create_vertex_buffer(device, vertex_capacity);
+
+        Self {
+            font,
+            glyphs: HashMap::new(),
+            packer: RowPacker::default(),
+            uploads: Vec::new(),
+            atlas_full: false,
+            atlas,
+            atlas_bind_group,
+            screen_uniform,
+            render_pipeline,
+            vertices: Vec::new(),
+            vertex_buffer,
+            vertex_capacity,
+            prepared_vertices: 0,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Text Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<TextVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Queues `text` with its top left corner at (x, y) in pixels, at
+    // FONT_SIZE times `scale` pixels per em. Characters the font doesn't
+    // have show up as its missing glyph box.
+    pub fn queue_text(&mut self, text: &str, x: f32, y: f32, scale: f32, color: [f32; 4]) {
+        let font_size = FONT_SIZE * scale;
+        let line_metrics = self.font.horizontal_line_metrics(font_size)
+            .expect("The bundled font has horizontal metrics");
+
+        // Whole pixels, so the atlas texels line up with the screen's
+        let x = x.round();
+        let mut pen_x = x;
+        let mut baseline = (y + line_metrics.ascent).round();
+        let mut previous = None;
+        for c in text.chars() {
+            if c == '\n' {
+                pen_x = x;
+                baseline += line_metrics.new_line_size.round();
+                previous = None;
+                continue;
+            }
+
+            if let Some(kern) = previous.and_then(|previous| self.font.horizontal_kern(previous, c, font_size)) {
+                pen_x += kern;
+            }
+            previous = Some(c);
+
+            let glyph = match self.glyph(c, font_size) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            let x0 = (pen_x + glyph.offset[0]).round();
+            let y0 = baseline + glyph.offset[1];
+            let (x1, y1) = (x0 + glyph.size[0], y0 + glyph.size[1]);
+            let [u0, v0] = glyph.uv_min;
+            let [u1, v1] = glyph.uv_max;
+            pen_x += glyph.advance;
+
+            if glyph.size[0] == 0.0 || glyph.size[1] == 0.0 {
+                continue;
+            }
+            let vertex = |position: [f32; 2], tex_coords: [f32; 2]| TextVertex { position, tex_coords, color };
+            self.vertices.extend_from_slice(&[
+                vertex([x0, y0], [u0, v0]),
+                vertex([x0, y1], [u0, v1]),
+                vertex([x1, y1], [u1, v1]),
+                vertex([x0, y0], [u0, v0]),
+                vertex([x1, y1], [u1, v1]),
+                vertex([x1, y0], [u1, v0]),
+            ]);
+        }
+    }
+
+    // Rasterizes `c` the first time it's drawn at `font_size`. None when it
+    // doesn't fit in the atlas anymore.
+    fn glyph(&mut self, c: char, font_size: f32) -> Option<Glyph> {
+        if let Some(glyph) = self.glyphs.get(&(c, font_size.to_bits())) {
+            return Some(*glyph);
+        }
+
+        let (metrics, coverage) = self.font.rasterize(c, font_size);
+        let (width, height) = (metrics.width as u32, metrics.height as u32);
+        let (x, y) = match self.packer.allocate(width, height) {
+            Some(position) => position,
+            None => {
+                if !self.atlas_full {
+                    log::warn!("The font atlas is full, some text is missing this frame");
+                    self.atlas_full = true;
+                }
+                return None;
+            }
+        };
+        if width > 0 && height > 0 {
+            self.uploads.push(Upload { x, y, width, height, coverage });
+        }
+
+        let atlas_size = ATLAS_SIZE as f32;
+        let glyph = Glyph {
+            uv_min: [x as f32 / atlas_size, y as f32 / atlas_size],
+            uv_max: [(x + width) as f32 / atlas_size, (y + height) as f32 / atlas_size],
+            // fontdue measures ymin up from the baseline to the bottom edge
+            offset: [metrics.xmin as f32, -(metrics.ymin as f32 + height as f32)],
+            size: [width as f32, height as f32],
+            advance: metrics.advance_width,
+        };
+        self.glyphs.insert((c, font_size.to_bits()), glyph);
+        Some(glyph)
+    }
+
+    // Uploads everything queued since the last call and clears the queue.
+    // Has to happen before the encoder that draws it is submitted.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        screen_size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        for upload in self.uploads.drain(..) {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.atlas.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: upload.x, y: upload.y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &upload.coverage,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(upload.width),
+                    rows_per_image: std::num::NonZeroU32::new(upload.height),
+                },
+                wgpu::Extent3d {
+                    width: upload.width,
+                    height: upload.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        // This frame's text is already laid out, the next frame's glyphs
+        // are written over the old ones once this frame is submitted
+        if self.atlas_full {
+            self.glyphs.clear();
+            self.packer = RowPacker::default();
+            self.atlas_full = false;
+        }
+
+        self.prepared_vertices = self.vertices.len() as u32;
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        self.screen_uniform.update(queue, ScreenUniform {
+            size: [screen_size.width as f32, screen_size.height as f32],
+            _pad: [0.0; 2],
+        });
+
+        if self.vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = self.vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.vertex_capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+
+        self.vertices.clear();
+    }
+
+    // Draws the text from the last prepare into `view`, keeping its current
+    // contents.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if self.prepared_vertices == 0 {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Draw over the scene instead of clearing it
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.atlas_bind_group, &[]);
+        render_pass.set_bind_group(1, self.screen_uniform.bind_group(), &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.prepared_vertices, 0..1);
+    }
+}
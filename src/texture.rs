@@ -1,6 +1,34 @@
 use image::GenericImageView;
 use anyhow::*;
 
+// Scales and offsets texture coordinates in the fragment shader, so a
+// texture can be tiled without touching the vertices.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TextureTransform {
+    pub scale: [f32; 2],
+    pub offset: [f32; 2],
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self {
+            scale: [1.0, 1.0],
+            offset: [0.0, 0.0],
+        }
+    }
+}
+
+// How the scene's textures are sampled. Nearest keeps pixel art crisp
+// instead of blurring it, and changes the texture bind group layout to
+// the NonFiltering sampler binding, see StateConfig::texture_filter.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TextureFilter {
+    #[default]
+    Linear,
+    Nearest,
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -8,14 +36,427 @@ pub struct Texture {
 }
 
 impl Texture {
+    // With a stencil aspect for masking, see State::set_stencil
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+    // The highest anisotropy_clamp wgpu accepts
+    pub const MAX_ANISOTROPY: u8 = 16;
+
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32, label: &str) -> Self {
+        // The depth texture has to be the same size as the surface
+        // we render to, so it is recreated whenever the surface resizes.
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                // Has to match the sample count of the color attachment
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::DEPTH_FORMAT,
+                // We render to this texture, and TEXTURE_BINDING lets us
+                // sample it later if we want to visualize the depth,
+                // through a view of just the DepthOnly aspect.
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            }
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            // Only needed if we want to use the texture as a comparison sampler
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler
+        }
+    }
+
+    // A color target the scene can be rendered into and then sampled from,
+    // e.g. by a post-processing pass. Like the depth texture it has to be
+    // recreated when the surface resizes.
+    pub fn create_render_target(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: config.width,
+                    height: config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                // Same format as the surface so the scene pipeline can target either
+                format: config.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            }
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler
+        }
+    }
 
     pub fn from_bytes(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
         Self::from_image(device, queue, &img, Some(label))
     }
 
+    // The sampler decides which binding type it needs in the bind group
+    // layout: anything using `FilterMode::Linear` needs
+    // `SamplerBindingType::Filtering`, a `compare` function needs
+    // `Comparison`, and all-`Nearest` samplers also work with
+    // `NonFiltering`. Address modes don't affect the binding type.
+    pub fn from_bytes_with_sampler(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str, sampler: &wgpu::SamplerDescriptor) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image_with_mip_levels(device, queue, &img, Some(label), 1, sampler)
+    }
+
+    // Synchronous file IO isn't available in the browser
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_path<P: AsRef<std::path::Path>>(device: &wgpu::Device, queue: &wgpu::Queue, path: P, label: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let format = image::ImageFormat::from_path(path)
+            .with_context(|| format!("Couldn't determine the image format of {}", path.display()))?;
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        let img = image::load_from_memory_with_format(&bytes, format)?;
+        Self::from_image(device, queue, &img, Some(label))
+    }
+
+    // Downloads and decodes an image, so the web build doesn't need to embed
+    // it with include_bytes!.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn from_url(device: &wgpu::Device, queue: &wgpu::Queue, url: &str, label: &str) -> Result<Self> {
+        use wasm_bindgen::JsCast;
+        use wasm_bindgen_futures::JsFuture;
+
+        // JsValue errors don't implement Error, so they're formatted instead
+        let window = web_sys::window().context("No window to fetch from")?;
+        let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+            .await
+            .map_err(|err| anyhow!("Couldn't fetch {}: {:?}", url, err))?
+            .dyn_into()
+            .map_err(|err| anyhow!("fetch didn't return a Response: {:?}", err))?;
+        if !response.ok() {
+            bail!("Fetching {} failed with status {}", url, response.status());
+        }
+
+        let array_buffer = response.array_buffer()
+            .map_err(|err| anyhow!("Couldn't read the body of {}: {:?}", url, err))?;
+        let array_buffer = JsFuture::from(array_buffer)
+            .await
+            .map_err(|err| anyhow!("Couldn't read the body of {}: {:?}", url, err))?;
+        let bytes = js_sys::Uint8Array::new(&array_buffer).to_vec();
+
+        Self::from_bytes(device, queue, &bytes, label)
+    }
+
+    // The native counterpart of the wasm from_url, downloading with a
+    // blocking client. Despite being async it blocks the thread polling it
+    // until the download finishes or times out, so await it where that's
+    // fine, e.g. under pollster::block_on before the event loop starts.
+    #[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+    pub async fn from_url(device: &wgpu::Device, queue: &wgpu::Queue, url: &str, label: &str) -> Result<Self> {
+        let bytes = crate::http::get(url)?;
+        Self::from_bytes(device, queue, &bytes, label)
+    }
+
+    pub fn from_bytes_with_mipmaps(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        let (width, height) = img.dimensions();
+        Self::from_image_with_mip_levels(device, queue, &img, Some(label), Self::mip_level_count(width, height), &Self::default_sampler())
+    }
+
+    // Anisotropic filtering keeps textures sharp when viewed at grazing
+    // angles. It only works with mipmaps and Linear for every filter, so the
+    // full mip chain is generated. `max_aniso` has to be 1, 2, 4, 8 or 16.
+    // Devices without `DownlevelFlags::ANISOTROPIC_FILTERING` ignore it and
+    // fall back to plain trilinear filtering.
+    pub fn from_bytes_anisotropic(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str, max_aniso: u8) -> Result<Self> {
+        if !max_aniso.is_power_of_two() || max_aniso > Self::MAX_ANISOTROPY {
+            bail!("Anisotropy level {} isn't a power of two up to {}", max_aniso, Self::MAX_ANISOTROPY);
+        }
+
+        let img = image::load_from_memory(bytes)?;
+        let (width, height) = img.dimensions();
+        let sampler = wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: std::num::NonZeroU8::new(max_aniso),
+            ..Self::default_sampler()
+        };
+        Self::from_image_with_mip_levels(device, queue, &img, Some(label), Self::mip_level_count(width, height), &sampler)
+    }
+
+    // Uploads block compressed data as is, e.g. the payload of a DDS or KTX2
+    // file. `data` holds every mip level back to back starting with the
+    // full size one, and the level count follows from its length. The
+    // device needs the feature the format requires, such as
+    // `Features::TEXTURE_COMPRESSION_BC` for the BC formats, which WebGL
+    // never has.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Result<Self> {
+        let info = format.describe();
+        if !info.is_compressed() {
+            bail!("{:?} isn't a block compressed format", format);
+        }
+        if !device.features().contains(info.required_features) {
+            bail!("{:?} needs {:?}, which this device doesn't support", format, info.required_features);
+        }
+        let (block_width, block_height) = (info.block_dimensions.0 as u32, info.block_dimensions.1 as u32);
+        if width == 0 || height == 0 || !width.is_multiple_of(block_width) || !height.is_multiple_of(block_height) {
+            bail!("{}x{} isn't a multiple of {:?}'s {}x{} blocks", width, height, format, block_width, block_height);
+        }
+
+        // Each level's size in blocks, rounded up since the smallest levels
+        // still take up a whole block
+        let level_layout = |mip_level: u32| {
+            let blocks_x = (width >> mip_level).max(1).div_ceil(block_width);
+            let blocks_y = (height >> mip_level).max(1).div_ceil(block_height);
+            let bytes_per_row = blocks_x * info.block_size as u32;
+            (blocks_x, blocks_y, bytes_per_row, (bytes_per_row * blocks_y) as usize)
+        };
+
+        let mut mip_level_count = 0;
+        let mut offset = 0;
+        while mip_level_count < Self::mip_level_count(width, height) {
+            let (_, _, _, level_bytes) = level_layout(mip_level_count);
+            if offset + level_bytes > data.len() {
+                break;
+            }
+            offset += level_bytes;
+            mip_level_count += 1;
+        }
+        if mip_level_count == 0 {
+            bail!("{} needs at least {} bytes for its first level, got {}", label, level_layout(0).3, data.len());
+        }
+        if offset != data.len() {
+            bail!("{} has {} bytes left over after its {} mip levels", label, data.len() - offset, mip_level_count);
+        }
+
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                label: Some(label),
+            }
+        );
+
+        let mut offset = 0;
+        for mip_level in 0..mip_level_count {
+            let (blocks_x, blocks_y, bytes_per_row, level_bytes) = level_layout(mip_level);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &data[offset..offset + level_bytes],
+                // Rows are rows of blocks here, not of texels
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(bytes_per_row),
+                    rows_per_image: None,
+                },
+                // Copies cover whole blocks, even past the edge of small levels
+                wgpu::Extent3d {
+                    width: blocks_x * block_width,
+                    height: blocks_y * block_height,
+                    depth_or_array_layers: 1,
+                },
+            );
+            offset += level_bytes;
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&Self::default_sampler());
+
+        Ok(Self {
+            texture,
+            view,
+            sampler
+        })
+    }
+
     pub fn from_image(device: &wgpu::Device, queue: &wgpu::Queue, img: &image::DynamicImage, label: Option<&str>) -> Result<Self> {
-		let rgba = img.to_rgba8();
+        Self::from_image_with_mip_levels(device, queue, img, label, 1, &Self::default_sampler())
+    }
+
+    // For pixel art: every filter is Nearest, so texels stay sharp squares
+    // when magnified, and there are no mipmaps to blur them when minified.
+    // The sampler only needs a `SamplerBindingType::NonFiltering` binding,
+    // with `TextureSampleType::Float { filterable: false }` for the texture.
+    pub fn from_bytes_nearest(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image_nearest(device, queue, &img, Some(label))
+    }
+
+    pub fn from_image_nearest(device: &wgpu::Device, queue: &wgpu::Queue, img: &image::DynamicImage, label: Option<&str>) -> Result<Self> {
+        Self::from_image_with_mip_levels(device, queue, img, label, 1, &Self::nearest_sampler())
+    }
+
+    // The sampler used by the constructors that don't take one.
+    pub fn default_sampler() -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            // Repeat so the texture tiles when its coordinates are scaled up
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            // Blend between mip levels, this has no effect on textures without mipmaps
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        }
+    }
+
+    // Faces are in the order +X, -X, +Y, -Y, +Z, -Z, e.g.
+    // `[include_bytes!("sky_px.png"), include_bytes!("sky_nx.png"), ...]`.
+    pub fn from_cubemap(device: &wgpu::Device, queue: &wgpu::Queue, faces: [&[u8]; 6], label: &str) -> Result<Self> {
+        let mut images = Vec::with_capacity(6);
+        for (index, bytes) in faces.iter().enumerate() {
+            images.push(image::load_from_memory(bytes)
+                .with_context(|| format!("Couldn't load face {} of {}", index, label))?);
+        }
+
+        Self::from_cubemap_images(device, queue, &images, Some(label))
+    }
+
+    // Same as from_cubemap for images that are already decoded.
+    pub fn from_cubemap_images(device: &wgpu::Device, queue: &wgpu::Queue, faces: &[image::DynamicImage], label: Option<&str>) -> Result<Self> {
+        if faces.len() != 6 {
+            bail!("A cubemap needs 6 faces, got {}", faces.len());
+        }
+        let (width, height) = faces[0].dimensions();
+        if width != height || faces.iter().any(|face| face.dimensions() != (width, height)) {
+            bail!("Cubemap faces have to be square and all the same size");
+        }
+
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                // A cubemap is a 2D texture with one array layer per face
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 6,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                label,
+            }
+        );
+
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &face.to_rgba8(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler
+        })
+    }
+
+    // The default sampler with every filter set to Nearest
+    pub fn nearest_sampler() -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Self::default_sampler()
+        }
+    }
+
+    // Number of levels in a full mip chain, down to and including 1x1.
+    pub fn mip_level_count(width: u32, height: u32) -> u32 {
+        32 - width.max(height).max(1).leading_zeros()
+    }
+
+    fn from_image_with_mip_levels(device: &wgpu::Device, queue: &wgpu::Queue, img: &image::DynamicImage, label: Option<&str>, mip_level_count: u32, sampler: &wgpu::SamplerDescriptor) -> Result<Self> {
 		let dimensions = img.dimensions();
 
 		let texture_size = wgpu::Extent3d {
@@ -29,7 +470,7 @@ impl Texture {
 				// All textures are stored in 3D, we represent our 2D texture
 				// by setting depth to 1.
 				size: texture_size,
-				mip_level_count: 1,
+				mip_level_count,
 				sample_count: 1,
 				dimension: wgpu::TextureDimension::D2,
 				// Most images are stored using sRGB so we need to reflect that here.
@@ -37,41 +478,51 @@ impl Texture {
 				// TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
 				// COPY_DST means that we want to copy data to this texture
 				usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-				label: label,
+				label,
 			}
 		);
 
-		queue.write_texture(
-			// Tells wgpu where to copy the pixel data
-			wgpu::ImageCopyTexture {
-				texture: &texture,
-				mip_level: 0,
-				origin: wgpu::Origin3d::ZERO,
-				aspect: wgpu::TextureAspect::All,
-			},
-			// The actual pixel data
-			&rgba,
-			// The layout of the texture
-			wgpu::ImageDataLayout {
-				offset: 0,
-				bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-				rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-			},
-			texture_size,
-		);
+		// Each mip level is downsampled on the CPU from the previous one
+		let mut level_image = img.clone();
+		for mip_level in 0..mip_level_count {
+			if mip_level > 0 {
+				level_image = level_image.resize_exact(
+					(dimensions.0 >> mip_level).max(1),
+					(dimensions.1 >> mip_level).max(1),
+					image::imageops::FilterType::Triangle,
+				);
+			}
+			let rgba = level_image.to_rgba8();
+			let level_dimensions = level_image.dimensions();
+
+			queue.write_texture(
+				// Tells wgpu where to copy the pixel data
+				wgpu::ImageCopyTexture {
+					texture: &texture,
+					mip_level,
+					origin: wgpu::Origin3d::ZERO,
+					aspect: wgpu::TextureAspect::All,
+				},
+				// The actual pixel data
+				&rgba,
+				// The layout of the texture
+				wgpu::ImageDataLayout {
+					offset: 0,
+					bytes_per_row: std::num::NonZeroU32::new(4 * level_dimensions.0),
+					rows_per_image: std::num::NonZeroU32::new(level_dimensions.1),
+				},
+				wgpu::Extent3d {
+					width: level_dimensions.0,
+					height: level_dimensions.1,
+					depth_or_array_layers: 1,
+				},
+			);
+		}
 
         // We don't need to configure the texture view much, so let's
         // let wgpu define it.
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let sampler = device.create_sampler(sampler);
 
 		Ok(Self {
             texture,
@@ -79,4 +530,20 @@ impl Texture {
             sampler
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_level_count_goes_down_to_1x1() {
+        // 256, 128, 64, 32, 16, 8, 4, 2, 1
+        assert_eq!(Texture::mip_level_count(256, 256), 9);
+        // The larger side decides, the shorter one stops halving at 1
+        assert_eq!(Texture::mip_level_count(256, 16), 9);
+        // Odd sizes round down at each level, 257 still gives 9 levels
+        assert_eq!(Texture::mip_level_count(257, 1), 9);
+        assert_eq!(Texture::mip_level_count(1, 1), 1);
+    }
+}
@@ -0,0 +1,70 @@
+use cgmath::{Matrix4, One, Quaternion, Vector3};
+
+// Position, orientation and size of an object in the world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    // Scales first, then rotates, then translates.
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // Column major, ready to be cast into a vertex or uniform buffer.
+    pub fn to_raw(&self) -> [[f32; 4]; 4] {
+        self.to_matrix().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, InnerSpace, Point3, Rotation3, Transform as _};
+
+    fn transform() -> Transform {
+        Transform {
+            translation: Vector3::new(1.0, 0.0, 0.0),
+            rotation: Quaternion::from_angle_z(Deg(90.0)),
+            scale: Vector3::new(2.0, 1.0, 1.0),
+        }
+    }
+
+    fn assert_close(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).magnitude2() < 1e-10, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn scales_then_rotates_then_translates() {
+        // Scaled to (2, 0, 0), rotated to (0, 2, 0), moved to (1, 2, 0). Any
+        // other order puts the point somewhere else.
+        let point = transform().to_matrix().transform_point(Point3::new(1.0, 0.0, 0.0));
+        assert_close(point, Point3::new(1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn matches_manual_composition() {
+        let transform = transform();
+        let manual = Matrix4::from_translation(transform.translation)
+            * Matrix4::from(transform.rotation)
+            * Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0);
+        assert_eq!(transform.to_matrix(), manual);
+        let raw: [[f32; 4]; 4] = manual.into();
+        assert_eq!(transform.to_raw(), raw);
+    }
+}
@@ -0,0 +1,221 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use crate::bind_group::{BindGroupBuilder, LayoutCache};
+
+// A uniform value along with the buffer and bind group that expose it to
+// shaders. The value is bound at binding 0 and visible to both stages.
+pub struct Uniform<T: bytemuck::Pod + bytemuck::Zeroable> {
+    value: T,
+    buffer: wgpu::Buffer,
+    layout: Rc<wgpu::BindGroupLayout>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl<T: bytemuck::Pod + bytemuck::Zeroable> Uniform<T> {
+    pub fn new(device: &wgpu::Device, value: T) -> Self {
+        Self::new_cached(device, &mut LayoutCache::new(), value)
+    }
+
+    // All uniforms have the same layout, so ones created through the same
+    // cache share it.
+    pub fn new_cached(device: &wgpu::Device, cache: &mut LayoutCache, value: T) -> Self {
+        let name = std::any::type_name::<T>();
+
+        let buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Buffer", name)),
+                contents: bytemuck::cast_slice(&[value]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let bind_group_label = format!("{} Bind Group", name);
+        let (layout, bind_group) = BindGroupBuilder::new()
+            .label(&bind_group_label)
+            .uniform(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT, &buffer)
+            .build_cached(device, cache);
+
+        Self {
+            value,
+            buffer,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    // Stores the new value and schedules it to be written to the buffer.
+    pub fn update(&mut self, queue: &wgpu::Queue, value: T) {
+        self.value = value;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[value]));
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+}
+
+// Rounds `size` up to the next multiple of `alignment`, which wgpu
+// guarantees is a power of two
+pub fn align_to(size: wgpu::BufferAddress, alignment: wgpu::BufferAddress) -> wgpu::BufferAddress {
+    (size + alignment - 1) & !(alignment - 1)
+}
+
+// Many values of the same uniform type in one buffer, e.g. a transform per
+// object. Each value sits in its own slot, aligned to
+// `min_uniform_buffer_offset_alignment`, and the shared bind group is bound
+// with the slot's offset:
+//
+//     render_pass.set_bind_group(index, array.bind_group(), &[array.offset(slot)]);
+//
+// That's one buffer and one bind group instead of one each per value. The
+// shader declares a plain `var<uniform>` of T, the offset picks which slot
+// it sees.
+pub struct UniformArray<T: bytemuck::Pod + bytemuck::Zeroable> {
+    values: Vec<T>,
+    // Distance between slots in bytes
+    stride: wgpu::BufferAddress,
+    // How many slots fit in buffer
+    capacity: usize,
+    buffer: wgpu::Buffer,
+    layout: Rc<wgpu::BindGroupLayout>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl<T: bytemuck::Pod + bytemuck::Zeroable> UniformArray<T> {
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        Self::new_cached(device, &mut LayoutCache::new(), capacity)
+    }
+
+    pub fn new_cached(device: &wgpu::Device, cache: &mut LayoutCache, capacity: usize) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+        let stride = align_to(std::mem::size_of::<T>() as wgpu::BufferAddress, alignment);
+        let capacity = capacity.max(1);
+        let buffer = Self::create_buffer(device, stride, capacity);
+        let (layout, bind_group) = Self::bind_group_builder(&buffer).build_cached(device, cache);
+
+        Self {
+            values: Vec::new(),
+            stride,
+            capacity,
+            buffer,
+            layout,
+            bind_group,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, stride: wgpu::BufferAddress, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} Array Buffer", std::any::type_name::<T>())),
+            size: stride * capacity as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn bind_group_builder(buffer: &wgpu::Buffer) -> BindGroupBuilder<'_> {
+        let size = wgpu::BufferSize::new(std::mem::size_of::<T>() as wgpu::BufferAddress)
+            .expect("uniform types can't be zero sized");
+        let mut builder = BindGroupBuilder::new();
+        builder.uniform_dynamic(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT, buffer, size);
+        builder
+    }
+
+    // Adds a value and returns its slot. Nothing reaches the GPU until upload.
+    pub fn push(&mut self, value: T) -> usize {
+        self.values.push(value);
+        self.values.len() - 1
+    }
+
+    pub fn set(&mut self, slot: usize, value: T) {
+        self.values[slot] = value;
+    }
+
+    pub fn get(&self, slot: usize) -> &T {
+        &self.values[slot]
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // The dynamic offset that selects `slot` in set_bind_group
+    pub fn offset(&self, slot: usize) -> wgpu::DynamicOffset {
+        (slot as wgpu::BufferAddress * self.stride) as wgpu::DynamicOffset
+    }
+
+    // Writes every slot to the buffer. If the values outgrew it, the buffer
+    // and bind group are recreated, so the bind group has to be fetched
+    // again afterwards. The layout stays the same.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.reserve(device);
+        if !self.values.is_empty() {
+            queue.write_buffer(&self.buffer, 0, &self.bytes());
+        }
+    }
+
+    // Like upload, but records the copy into `encoder` through a staging
+    // belt, which has to be finished before the encoder is submitted.
+    pub fn write_with_belt(
+        &mut self,
+        belt: &mut wgpu::util::StagingBelt,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+    ) {
+        self.reserve(device);
+        let bytes = self.bytes();
+        if let Some(size) = wgpu::BufferSize::new(bytes.len() as wgpu::BufferAddress) {
+            belt.write_buffer(encoder, &self.buffer, 0, size, device).copy_from_slice(&bytes);
+        }
+    }
+
+    fn reserve(&mut self, device: &wgpu::Device) {
+        if self.values.len() > self.capacity {
+            self.capacity = self.values.len().max(self.capacity * 2);
+            self.buffer = Self::create_buffer(device, self.stride, self.capacity);
+            self.bind_group = Self::bind_group_builder(&self.buffer).build_bind_group(device, &self.layout);
+        }
+    }
+
+    // The values laid out with the padding between slots
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.stride as usize * self.values.len()];
+        for (value, slot) in self.values.iter().zip(bytes.chunks_mut(self.stride as usize)) {
+            slot[..std::mem::size_of::<T>()].copy_from_slice(bytemuck::bytes_of(value));
+        }
+        bytes
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+}